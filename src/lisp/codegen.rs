@@ -0,0 +1,488 @@
+use vm::{
+    Op,
+    OpCode,
+    Bin,
+    IdentID,
+    ConstID,
+    Quantif,
+    MemData,
+    Error,
+    PrintMode,
+};
+
+use super::ir::{
+    Ir,
+    Prim,
+};
+
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+
+/// Scratch variable holding a computed callee for `CLL`
+const CALLEE_VAR: &str = "%callee";
+
+/// A frame the generated code will push, with the names it binds
+struct Scope {
+    names: HashSet<String>,
+    /// Names already bound at the current point of the generated code
+    defined: HashSet<String>,
+}
+
+impl Scope {
+    fn new(names: HashSet<String>) -> Self {
+        Self { names, defined: HashSet::new() }
+    }
+}
+
+/// Names a sequence defines in the frame it runs in
+fn defined_names(body: &[Ir], out: &mut HashSet<String>) {
+    for ir in body {
+        match *ir {
+            Ir::DefineVar(ref name, ref val) => {
+                out.insert(name.clone());
+                defined_names(::std::slice::from_ref(&**val), out);
+            },
+            Ir::DefineFun(ref name, ..) => { out.insert(name.clone()); },
+            Ir::Set(_, ref val) => defined_names(::std::slice::from_ref(&**val), out),
+            Ir::Seq(ref body) => defined_names(body, out),
+            // These run in frames of their own
+            Ir::Const(..) | Ir::Var(..) | Ir::Do(..) | Ir::Lambda(..) => (),
+            Ir::If(ref c, ref t, ref e) => {
+                defined_names(::std::slice::from_ref(&**c), out);
+                defined_names(::std::slice::from_ref(&**t), out);
+                if let Some(ref e) = *e {
+                    defined_names(::std::slice::from_ref(&**e), out);
+                }
+            },
+            Ir::And(ref args) | Ir::Or(ref args) | Ir::Prim(_, ref args) =>
+                defined_names(args, out),
+            Ir::CallFun(ref callee, ref args) => {
+                defined_names(::std::slice::from_ref(&**callee), out);
+                defined_names(args, out);
+            },
+        }
+    }
+}
+
+/// Variables a function body reads without binding them itself, in order of appearance
+fn free_vars(params: &[String], body: &[Ir]) -> Vec<String> {
+    fn walk(ir: &Ir, bound: &HashSet<String>, out: &mut Vec<String>) {
+        match *ir {
+            Ir::Const(..) => (),
+            Ir::Var(ref name) => {
+                if !bound.contains(name) && !out.contains(name) {
+                    out.push(name.clone());
+                }
+            },
+            Ir::Do(ref body) => {
+                let mut inner = bound.clone();
+                defined_names(body, &mut inner);
+                body.iter().for_each(|ir| walk(ir, &inner, out));
+            },
+            Ir::DefineVar(_, ref val) => walk(val, bound, out),
+            Ir::Seq(ref body) => body.iter().for_each(|ir| walk(ir, bound, out)),
+            Ir::Set(ref name, ref val) => {
+                walk(&Ir::Var(name.clone()), bound, out);
+                walk(val, bound, out);
+            },
+            Ir::DefineFun(_, ref params, ref body) | Ir::Lambda(ref params, ref body) => {
+                let mut inner = bound.clone();
+                inner.extend(params.iter().cloned());
+                defined_names(body, &mut inner);
+                body.iter().for_each(|ir| walk(ir, &inner, out));
+            },
+            Ir::If(ref c, ref t, ref e) => {
+                walk(c, bound, out);
+                walk(t, bound, out);
+                if let Some(ref e) = *e { walk(e, bound, out); }
+            },
+            Ir::And(ref args) | Ir::Or(ref args) | Ir::Prim(_, ref args) =>
+                args.iter().for_each(|ir| walk(ir, bound, out)),
+            Ir::CallFun(ref callee, ref args) => {
+                walk(callee, bound, out);
+                args.iter().for_each(|ir| walk(ir, bound, out));
+            },
+        }
+    }
+
+    let mut out = Vec::new();
+    walk(&Ir::Lambda(params.to_vec(), body.to_vec()), &HashSet::new(), &mut out);
+    out
+}
+
+/// Emits a `Bin` from IR, interning identifiers and deduplicating constants
+pub struct CodeGen {
+    insts: Vec<Op>,
+    idents: Vec<IdentID>,
+    var_strings: HashMap<IdentID, String>,
+    ident_ids: HashMap<String, IdentID>,
+    consts: Vec<MemData>,
+    /// Frames enclosing the code being generated, innermost last; the global
+    /// scope isn't one of them
+    scopes: Vec<Scope>,
+}
+
+impl CodeGen {
+    pub fn new() -> Self {
+        Self {
+            insts: Vec::new(),
+            idents: Vec::new(),
+            var_strings: HashMap::new(),
+            ident_ids: HashMap::new(),
+            consts: Vec::new(),
+            scopes: Vec::new(),
+        }
+    }
+
+    fn ident(&mut self, name: &str) -> Result<IdentID, Error> {
+        if let Some(id) = self.ident_ids.get(name) {
+            return Ok(*id);
+        }
+        if self.idents.len() > IdentID::MAX as usize {
+            return Err(Error::CompileError("too many identifiers", name.to_owned()));
+        }
+
+        let id = self.idents.len() as IdentID;
+        self.idents.push(id);
+        self.var_strings.insert(id, name.to_owned());
+        self.ident_ids.insert(name.to_owned(), id);
+        Ok(id)
+    }
+
+    fn konst(&mut self, val: &MemData) -> Result<ConstID, Error> {
+        if let Some(i) = self.consts.iter().position(|c| c.is_eq(val)) {
+            return Ok(i as ConstID);
+        }
+        if self.consts.len() > ConstID::MAX as usize {
+            return Err(Error::CompileError("too many constants", val.print(PrintMode::Write)));
+        }
+
+        self.consts.push(val.clone());
+        Ok((self.consts.len() - 1) as ConstID)
+    }
+
+    #[inline]
+    fn push(&mut self, opcode: OpCode, ident: Option<IdentID>, n: Option<Quantif>, mute: bool) {
+        self.insts.push(Op::new(opcode, ident, n, None, None, mute))
+    }
+
+    fn push_const(&mut self, val: &MemData) -> Result<(), Error> {
+        let c = self.konst(val)?;
+        self.insts.push(Op::new(OpCode::LVR, None, None, Some(c), None, false));
+        Ok(())
+    }
+
+    /// Pops the last register value without using it
+    fn drop_value(&mut self) -> Result<(), Error> {
+        self.push(OpCode::RRR, None, Some(1), false);
+        Ok(())
+    }
+
+    /// Records `name` as bound from here on in the innermost frame
+    fn mark_defined(&mut self, name: &str) {
+        if let Some(s) = self.scopes.last_mut() {
+            s.defined.insert(name.to_owned());
+        }
+    }
+
+    /// Local variables a function has to capture, or `None` if one of them
+    /// isn't bound yet, in which case it needs its whole defining environment
+    fn captures(&self, params: &[String], body: &[Ir]) -> Option<Vec<String>> {
+        let mut r = Vec::new();
+        for name in free_vars(params, body) {
+            match self.scopes.iter().rev().find(|s| s.names.contains(&name)) {
+                Some(s) if s.defined.contains(&name) => r.push(name),
+                Some(_) => return None,
+                // Globals are found through the global scope at call time
+                None => (),
+            }
+        }
+        Some(r)
+    }
+
+    /// Emits `body` into its own instruction list and returns it
+    fn record<F>(&mut self, body: F) -> Result<Vec<Op>, Error>
+        where F: FnOnce(&mut Self) -> Result<(), Error> {
+
+        let outer = ::std::mem::take(&mut self.insts);
+        let r = body(self);
+        let insts = ::std::mem::replace(&mut self.insts, outer);
+        r.map(|_| insts)
+    }
+
+    /// Emits `REC n` followed by the recorded body, then `opcode n` to wrap it up
+    fn emit_recorded(&mut self, body: Vec<Op>, opcode: OpCode) {
+        let n = body.len() as Quantif;
+        self.push(OpCode::REC, None, Some(n), false);
+        self.insts.extend(body);
+        self.push(opcode, None, Some(n), false);
+    }
+
+    /// Emits a sequence whose last value is kept only if `used`
+    fn emit_seq(&mut self, body: &[Ir], used: bool) -> Result<(), Error> {
+        if body.is_empty() {
+            return if used { self.push_const(&MemData::Nil) } else { Ok(()) };
+        }
+        let last = body.len() - 1;
+        for (i, ir) in body.iter().enumerate() {
+            self.emit(ir, used && i == last)?;
+        }
+        Ok(())
+    }
+
+    /// Emits a function value; `define` names it in the current frame as well
+    fn emit_lambda(&mut self, params: &[String], body: &[Ir], define: Option<&str>, used: bool)
+        -> Result<(), Error> {
+
+        let mut names: HashSet<String> = params.iter().cloned().collect();
+        defined_names(body, &mut names);
+        let mut scope = Scope::new(names);
+        scope.defined.extend(params.iter().cloned());
+
+        self.scopes.push(scope);
+        let insts = self.record(|g| {
+            g.push(OpCode::PSS, None, None, false);
+            // Arguments are pushed in order, so the last one is on top
+            for p in params.iter().rev() {
+                let id = g.ident(p)?;
+                g.push(OpCode::DVR, Some(id), None, true);
+            }
+            g.emit_seq(body, true)?;
+            g.push(OpCode::PPS, None, None, false);
+            Ok(())
+        });
+        self.scopes.pop();
+        let insts = insts?;
+
+        let define = match define {
+            Some(name) => Some(self.ident(name)?),
+            None => None,
+        };
+        match self.captures(params, body) {
+            Some(captures) => {
+                self.emit_recorded(insts, OpCode::PRC);
+                if captures.is_empty() {
+                    self.push(OpCode::CAP, None, None, false);
+                }
+                for name in captures {
+                    let id = self.ident(&name)?;
+                    self.push(OpCode::CAP, Some(id), None, false);
+                }
+                if define.is_some() {
+                    self.push(OpCode::DVR, define, None, !used);
+                }
+            },
+            None => {
+                let n = insts.len() as Quantif;
+                self.push(OpCode::REC, None, Some(n), false);
+                self.insts.extend(insts);
+                match define {
+                    Some(id) => self.push(OpCode::DFN, Some(id), Some(n), !used),
+                    None => self.push(OpCode::LMB, None, Some(n), false),
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Emits code for `ir`, leaving exactly one value in the register iff `used`
+    pub fn emit(&mut self, ir: &Ir, used: bool) -> Result<(), Error> {
+        match *ir {
+            Ir::Const(ref v) => {
+                if used { self.push_const(v)?; }
+            },
+            Ir::Var(ref name) => {
+                if used {
+                    let id = self.ident(name)?;
+                    self.push(OpCode::LVR, Some(id), None, false);
+                }
+            },
+            Ir::Do(ref body) => {
+                let mut names = HashSet::new();
+                defined_names(body, &mut names);
+
+                self.scopes.push(Scope::new(names));
+                self.push(OpCode::PSS, None, None, false);
+                let r = self.emit_seq(body, used);
+                self.push(OpCode::PPS, None, None, false);
+                self.scopes.pop();
+                r?;
+            },
+            Ir::Seq(ref body) => self.emit_seq(body, used)?,
+            Ir::DefineVar(ref name, ref val) => {
+                self.emit(val, true)?;
+                let id = self.ident(name)?;
+                self.push(OpCode::DVR, Some(id), None, !used);
+                self.mark_defined(name);
+            },
+            Ir::DefineFun(ref name, ref params, ref body) => {
+                self.emit_lambda(params, body, Some(name), used)?;
+                self.mark_defined(name);
+            },
+            Ir::Set(ref name, ref val) => {
+                self.emit(val, true)?;
+                let id = self.ident(name)?;
+                self.push(OpCode::SET, Some(id), None, !used);
+            },
+            Ir::Lambda(ref params, ref body) => {
+                if used { self.emit_lambda(params, body, None, true)?; }
+            },
+            Ir::If(ref cond, ref then, ref els) => {
+                let then = self.record(|g| g.emit(then, used))?;
+                self.emit_recorded(then, OpCode::PRC);
+
+                if els.is_none() && !used {
+                    self.emit(cond, true)?;
+                    self.push(OpCode::IFT, None, None, false);
+                } else {
+                    let els = self.record(|g| match *els {
+                        Some(ref e) => g.emit(e, used),
+                        None => g.push_const(&MemData::Nil),
+                    })?;
+                    self.emit_recorded(els, OpCode::PRC);
+                    self.emit(cond, true)?;
+                    self.push(OpCode::IFE, None, None, false);
+                }
+            },
+            Ir::And(ref args) | Ir::Or(ref args) => {
+                for a in args.iter() {
+                    let body = self.record(|g| g.emit(a, true))?;
+                    self.emit_recorded(body, OpCode::PRC);
+                }
+                let opcode = if let Ir::And(..) = *ir { OpCode::CAN } else { OpCode::COR };
+                self.push(opcode, None, Some(args.len() as Quantif), false);
+                if !used { self.drop_value()?; }
+            },
+            Ir::CallFun(ref callee, ref args) => {
+                for a in args.iter() {
+                    self.emit(a, true)?;
+                }
+                if let Ir::Var(ref name) = **callee {
+                    let id = self.ident(name)?;
+                    self.push(OpCode::CLL, Some(id), None, false);
+                } else {
+                    let id = self.ident(CALLEE_VAR)?;
+                    self.push(OpCode::PSS, None, None, false);
+                    self.emit(callee, true)?;
+                    self.push(OpCode::DVR, Some(id), None, true);
+                    self.push(OpCode::CLL, Some(id), None, false);
+                    self.push(OpCode::PPS, None, None, false);
+                }
+                if !used { self.drop_value()?; }
+            },
+            Ir::Prim(prim, ref args) => {
+                // `(- x)` negates, so it's emitted as `(- 0 x)`
+                let negate = prim == Prim::Sub && args.len() == 1;
+                if negate { self.push_const(&MemData::Int(0))?; }
+                for a in args.iter() {
+                    self.emit(a, true)?;
+                }
+                self.emit_prim(prim, args.len() as Quantif + negate as Quantif, used)?;
+            },
+        }
+        Ok(())
+    }
+
+    fn emit_prim(&mut self, prim: Prim, n: Quantif, used: bool) -> Result<(), Error> {
+        let opcode = match prim {
+            Prim::Display => {
+                self.push(OpCode::DSP, None, None, !used);
+                return Ok(());
+            },
+            Prim::Convert(typ) => {
+                self.insts.push(Op::new(OpCode::CNV, None, Some(1), None, Some(typ), false));
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::VectorSet | Prim::MapSet | Prim::MapDelete | Prim::SetCar | Prim::SetCdr => {
+                let opcode = match prim {
+                    Prim::VectorSet => OpCode::VST,
+                    Prim::MapSet    => OpCode::MST,
+                    Prim::MapDelete => OpCode::MDL,
+                    Prim::SetCar    => OpCode::SCA,
+                    _               => OpCode::SCD,
+                };
+                self.push(opcode, None, None, !used);
+                return Ok(());
+            },
+            Prim::Str(op) => {
+                self.push(OpCode::STR, None, Some(op.quantif()), false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::Char(op) => {
+                self.push(OpCode::CHR, None, Some(op.quantif()), false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::RecordType | Prim::RecordNew => {
+                let opcode = if prim == Prim::RecordType { OpCode::RTD } else { OpCode::RNW };
+                self.push(opcode, None, Some(n - 1), false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::RecordIs => {
+                self.push(OpCode::RIS, None, None, false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::RecordGet(i) => {
+                self.push(OpCode::RGT, None, Some(i), false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::RecordSet(i) => {
+                self.push(OpCode::RST, None, Some(i), !used);
+                return Ok(());
+            },
+            Prim::Map => {
+                self.push(OpCode::MAP, None, Some(n / 2), false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::Cons | Prim::Identical | Prim::Eqv | Prim::Equal | Prim::VectorRef
+                | Prim::VectorLength | Prim::VectorSlice | Prim::MapRef | Prim::MapKeys
+                | Prim::MapSize => {
+                let opcode = match prim {
+                    Prim::Cons         => OpCode::CNS,
+                    Prim::Identical    => OpCode::CEI,
+                    Prim::Eqv          => OpCode::CEV,
+                    Prim::Equal        => OpCode::CES,
+                    Prim::VectorRef    => OpCode::VRF,
+                    Prim::VectorLength => OpCode::VLN,
+                    Prim::VectorSlice  => OpCode::VSL,
+                    Prim::MapRef       => OpCode::MGT,
+                    Prim::MapKeys      => OpCode::MKS,
+                    _                  => OpCode::MLN,
+                };
+                self.push(opcode, None, None, false);
+                return if used { Ok(()) } else { self.drop_value() };
+            },
+            Prim::Add    => OpCode::ADD,
+            Prim::Sub    => OpCode::SUB,
+            Prim::Mul    => OpCode::MUL,
+            Prim::Div    => OpCode::DIV,
+            Prim::Gt     => OpCode::CGT,
+            Prim::Lt     => OpCode::CLT,
+            Prim::Eq     => OpCode::CEQ,
+            Prim::Not    => OpCode::CNT,
+            Prim::Car    => OpCode::CAR,
+            Prim::Cdr    => OpCode::CDR,
+            Prim::Concat => OpCode::CAT,
+            Prim::Vector => OpCode::VEC,
+        };
+        self.push(opcode, None, Some(n), false);
+        if used { Ok(()) } else { self.drop_value() }
+    }
+
+    /// Emits a top-level program whose value is the one of its last form
+    pub fn emit_program(&mut self, irs: &[Ir]) -> Result<(), Error> {
+        self.emit_seq(irs, true)
+    }
+
+    pub fn into_bin(self) -> Bin {
+        Bin::new(self.insts.into(), self.idents, self.var_strings, self.consts)
+    }
+}
+
+/// Generates a loadable `Bin` for a sequence of top-level forms
+pub fn generate(irs: &[Ir]) -> Result<Bin, Error> {
+    let mut g = CodeGen::new();
+    g.emit_program(irs)?;
+    Ok(g.into_bin())
+}
@@ -0,0 +1,261 @@
+use vm::{
+    MemData,
+    Error,
+    PrintMode,
+    Symbol,
+};
+
+use super::ir::{
+    Ir,
+    Prim,
+};
+
+#[inline]
+fn bad_form(msg: &'static str, form: &MemData) -> Error {
+    Error::CompileError(msg, form.print(PrintMode::Write))
+}
+
+fn symbol_name(form: &MemData) -> Option<&'static str> {
+    if let MemData::Symbol(s) = *form.deref() {
+        Some(s.as_str())
+    } else {
+        None
+    }
+}
+
+fn param_names(items: &[MemData]) -> Result<Vec<String>, Error> {
+    items.iter()
+        .map(|p| symbol_name(p)
+             .map(|s| s.to_owned())
+             .ok_or_else(|| bad_form("expected a parameter name", p)))
+        .collect()
+}
+
+fn params(form: &MemData) -> Result<Vec<String>, Error> {
+    param_names(&form.list_items()
+                .ok_or_else(|| bad_form("expected a parameter list", form))?)
+}
+
+fn body(form: &MemData, forms: &[MemData]) -> Result<Vec<Ir>, Error> {
+    if forms.is_empty() {
+        return Err(bad_form("expected at least one body form", form));
+    }
+    compile_all(forms)
+}
+
+/// Wraps a multi-form body so it can stand where a single expression is expected
+fn sequence(mut body: Vec<Ir>) -> Ir {
+    if body.len() == 1 {
+        body.pop().unwrap()
+    } else {
+        Ir::Do(body)
+    }
+}
+
+/// Lowers one form as returned by the reader to the IR
+pub fn compile(form: &MemData) -> Result<Ir, Error> {
+    match *form.deref() {
+        MemData::Symbol(s) => Ok(Ir::Var(s.as_str().to_owned())),
+        MemData::Pair(..) => {
+            let items = form.list_items()
+                .ok_or_else(|| bad_form("cannot evaluate an improper list", form))?;
+            compile_list(form, &items)
+        },
+        ref v => Ok(Ir::Const(v.clone())),
+    }
+}
+
+pub fn compile_all(forms: &[MemData]) -> Result<Vec<Ir>, Error> {
+    forms.iter().map(compile).collect()
+}
+
+/// Lowers `(define-record-type name (ctor field...) pred (field accessor [modifier])...)`
+/// to the definitions of the type and of its procedures
+fn record_type(form: &MemData, args: &[MemData]) -> Result<Ir, Error> {
+    if args.len() < 3 {
+        return Err(bad_form("define-record-type takes a name, a constructor, a predicate and fields", form));
+    }
+    let name = symbol_name(&args[0])
+        .ok_or_else(|| bad_form("expected a record type name", &args[0]))?;
+    let ctor = args[1].list_items()
+        .filter(|c| !c.is_empty())
+        .ok_or_else(|| bad_form("expected a `(constructor field...)` spec", &args[1]))?;
+    let pred = symbol_name(&args[2])
+        .ok_or_else(|| bad_form("expected a predicate name", &args[2]))?;
+
+    let mut fields = Vec::new();
+    let mut procs = Vec::new();
+    for (i, spec) in args[3..].iter().enumerate() {
+        let names = spec.list_items().map_or_else(|| Ok(Vec::new()), |s| param_names(&s))?;
+        if names.len() != 2 && names.len() != 3 {
+            return Err(bad_form("expected a `(field accessor [modifier])` spec", spec));
+        }
+        let get = Prim::RecordGet(i as u32);
+        procs.push(Ir::DefineFun(names[1].clone(), vec!["%record".to_owned()], vec![
+            Ir::Prim(get, vec![Ir::Var(name.to_owned()), Ir::Var("%record".to_owned())])]));
+        if let Some(modifier) = names.get(2) {
+            let set = Prim::RecordSet(i as u32);
+            procs.push(Ir::DefineFun(modifier.clone(), vec!["%record".to_owned(), "%value".to_owned()], vec![
+                Ir::Prim(set, vec![
+                    Ir::Var(name.to_owned()), Ir::Var("%record".to_owned()), Ir::Var("%value".to_owned())])]));
+        }
+        fields.push(names[0].clone());
+    }
+
+    // Parameters are renamed so that a field can't shadow the type's variable;
+    // fields the constructor doesn't take start out as nil
+    let ctor_fields = param_names(&ctor[1..])?;
+    if let Some(f) = ctor_fields.iter().position(|f| !fields.contains(f)) {
+        return Err(bad_form("unknown record field", &ctor[1 + f]));
+    }
+    let ctor_name = symbol_name(&ctor[0])
+        .ok_or_else(|| bad_form("expected a constructor name", &ctor[0]))?;
+    let mut values = vec![Ir::Var(name.to_owned())];
+    values.extend(fields.iter().map(|f| if ctor_fields.contains(f) {
+        Ir::Var(format!("%{}", f))
+    } else {
+        Ir::Const(MemData::Nil)
+    }));
+    procs.push(Ir::DefineFun(ctor_name.to_owned(),
+                             ctor_fields.iter().map(|f| format!("%{}", f)).collect(),
+                             vec![Ir::Prim(Prim::RecordNew, values)]));
+    procs.push(Ir::DefineFun(pred.to_owned(), vec!["%value".to_owned()], vec![
+        Ir::Prim(Prim::RecordIs, vec![Ir::Var(name.to_owned()), Ir::Var("%value".to_owned())])]));
+
+    // `<point>` names the type `point`; the type is defined last so it is the value of the form
+    let type_name = name.trim_start_matches('<').trim_end_matches('>');
+    let mut descr = vec![Ir::Const(MemData::Symbol(Symbol::intern(type_name)))];
+    descr.extend(fields.iter().map(|f| Ir::Const(MemData::Symbol(Symbol::intern(f)))));
+    procs.push(Ir::DefineVar(name.to_owned(), Box::new(Ir::Prim(Prim::RecordType, descr))));
+    Ok(Ir::Seq(procs))
+}
+
+fn compile_list(form: &MemData, items: &[MemData]) -> Result<Ir, Error> {
+    let head = symbol_name(&items[0]);
+    let args = &items[1..];
+
+    match head {
+        Some("do") | Some("begin") =>
+            Ok(Ir::Do(compile_all(args)?)),
+
+        Some("define") => {
+            if args.len() < 2 {
+                return Err(bad_form("malformed define", form));
+            }
+            if let Some(name) = symbol_name(&args[0]) {
+                if args.len() != 2 {
+                    return Err(bad_form("define of a variable takes exactly one value", form));
+                }
+                return Ok(Ir::DefineVar(name.to_owned(), Box::new(compile(&args[1])?)));
+            }
+
+            // (define (f args...) body...)
+            let sig = args[0].list_items()
+                .ok_or_else(|| bad_form("expected a name or a function signature", &args[0]))?;
+            let name = sig.first()
+                .and_then(symbol_name)
+                .ok_or_else(|| bad_form("expected a function name", &args[0]))?;
+            Ok(Ir::DefineFun(name.to_owned(), param_names(&sig[1..])?, body(form, &args[1..])?))
+        },
+
+        Some("set!") => {
+            if args.len() != 2 {
+                return Err(bad_form("set! takes a variable and exactly one value", form));
+            }
+            let name = symbol_name(&args[0])
+                .ok_or_else(|| bad_form("expected a variable name", &args[0]))?;
+            Ok(Ir::Set(name.to_owned(), Box::new(compile(&args[1])?)))
+        },
+
+        Some("lambda") => {
+            if args.is_empty() {
+                return Err(bad_form("malformed lambda", form));
+            }
+            Ok(Ir::Lambda(params(&args[0])?, body(form, &args[1..])?))
+        },
+
+        Some("let") => {
+            if args.is_empty() {
+                return Err(bad_form("malformed let", form));
+            }
+            let bindings = args[0].list_items()
+                .ok_or_else(|| bad_form("expected a binding list", &args[0]))?;
+
+            let mut block = Vec::with_capacity(bindings.len() + args.len() - 1);
+            for b in bindings.iter() {
+                let b_items = b.list_items().unwrap_or_default();
+                match (b_items.len(), b_items.first().and_then(symbol_name)) {
+                    (2, Some(name)) =>
+                        block.push(Ir::DefineVar(name.to_owned(), Box::new(compile(&b_items[1])?))),
+                    _ => return Err(bad_form("expected a `(name value)` binding", b)),
+                }
+            }
+            block.append(&mut body(form, &args[1..])?);
+            Ok(Ir::Do(block))
+        },
+
+        Some("if") => {
+            if args.len() != 2 && args.len() != 3 {
+                return Err(bad_form("if takes a condition, a consequent and an optional alternative", form));
+            }
+            Ok(Ir::If(
+                    Box::new(compile(&args[0])?),
+                    Box::new(compile(&args[1])?),
+                    match args.get(2) {
+                        Some(e) => Some(Box::new(compile(e)?)),
+                        None => None,
+                    }))
+        },
+
+        Some("cond") => {
+            // Build the nested `if` chain from the last clause outwards
+            let mut chain = None;
+            for clause in args.iter().rev() {
+                let c_items = clause.list_items().unwrap_or_default();
+                if c_items.len() < 2 {
+                    return Err(bad_form("expected a `(test body...)` clause", clause));
+                }
+                let then = sequence(compile_all(&c_items[1..])?);
+
+                if symbol_name(&c_items[0]) == Some("else") {
+                    if chain.is_some() {
+                        return Err(bad_form("else must be the last cond clause", clause));
+                    }
+                    chain = Some(then);
+                } else {
+                    chain = Some(Ir::If(
+                            Box::new(compile(&c_items[0])?),
+                            Box::new(then),
+                            chain.map(Box::new)));
+                }
+            }
+            Ok(chain.unwrap_or(Ir::Const(MemData::Nil)))
+        },
+
+        Some("and") =>
+            Ok(Ir::And(compile_all(args)?)),
+        Some("or") =>
+            Ok(Ir::Or(compile_all(args)?)),
+
+        Some("define-record-type") => record_type(form, args),
+
+        // The datum is kept as is, to be stored in the consts of the bin
+        Some("quote") => match args.len() {
+            1 => Ok(Ir::Const(args[0].deref().clone())),
+            _ => Err(bad_form("quote takes exactly one datum", form)),
+        },
+
+        Some(name) if Prim::from_name(name).is_some() => {
+            let prim = Prim::from_name(name).unwrap();
+            if prim.arity().is_some_and(|n| n != args.len()) {
+                return Err(bad_form("wrong number of arguments to primitive", form));
+            }
+            if prim == Prim::Map && !args.len().is_multiple_of(2) {
+                return Err(bad_form("make-map takes keys and values in pairs", form));
+            }
+            Ok(Ir::Prim(prim, compile_all(args)?))
+        },
+
+        _ => Ok(Ir::CallFun(Box::new(compile(&items[0])?), compile_all(args)?)),
+    }
+}
@@ -0,0 +1,159 @@
+use vm::{
+    MemData,
+    Type,
+    StrOp,
+    CharOp,
+};
+
+/// Intermediate representation produced by the compiler (see spec.md)
+///
+///     (let ((a 10)) (display (int->str a)))
+///
+/// lowers to
+///
+///     (do (define-var a 10) (display (int->str a)))
+#[derive(PartialEq, Debug, Clone)]
+pub enum Ir {
+    Const(MemData),
+    Var(String),
+    /// Evaluate in order inside a new scope, yielding the last value
+    Do(Vec<Ir>),
+    /// Evaluate in order in the current scope, for forms defining several names
+    Seq(Vec<Ir>),
+    DefineVar(String, Box<Ir>),
+    DefineFun(String, Vec<String>, Vec<Ir>),
+    /// Assigns to the variable in the nearest scope binding it
+    Set(String, Box<Ir>),
+    Lambda(Vec<String>, Vec<Ir>),
+    If(Box<Ir>, Box<Ir>, Option<Box<Ir>>),
+    /// Short-circuiting, yielding the value that decided the result
+    And(Vec<Ir>),
+    Or(Vec<Ir>),
+    CallFun(Box<Ir>, Vec<Ir>),
+    Prim(Prim, Vec<Ir>),
+}
+
+/// Operations backed directly by an opcode instead of a function call
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Gt,
+    Lt,
+    Eq,
+    Not,
+    /// `eq?`, `eqv?` and `equal?`
+    Identical,
+    Eqv,
+    Equal,
+    Cons,
+    Car,
+    Cdr,
+    Concat,
+    Display,
+    Convert(Type),
+    Vector,
+    VectorRef,
+    VectorSet,
+    VectorLength,
+    VectorSlice,
+    Map,
+    MapRef,
+    MapSet,
+    MapDelete,
+    MapKeys,
+    MapSize,
+    SetCar,
+    SetCdr,
+    Str(StrOp),
+    Char(CharOp),
+    /// Only generated by `define-record-type`: the type comes first, and the
+    /// field index is known at compile time
+    RecordType,
+    RecordNew,
+    RecordIs,
+    RecordGet(u32),
+    RecordSet(u32),
+}
+
+impl Prim {
+    pub fn from_name(name: &str) -> Option<Prim> {
+        Some(match name {
+            "+"       => Prim::Add,
+            "-"       => Prim::Sub,
+            "*"       => Prim::Mul,
+            "/"       => Prim::Div,
+            ">"       => Prim::Gt,
+            "<"       => Prim::Lt,
+            "="       => Prim::Eq,
+            "not"     => Prim::Not,
+            "eq?"     => Prim::Identical,
+            "eqv?"    => Prim::Eqv,
+            "equal?"  => Prim::Equal,
+            "cons"    => Prim::Cons,
+            "car"     => Prim::Car,
+            "cdr"     => Prim::Cdr,
+            "concat"  => Prim::Concat,
+            "display" => Prim::Display,
+            "int->str"    => Prim::Convert(Type::Str),
+            "float->str"  => Prim::Convert(Type::Str),
+            "char->str"   => Prim::Convert(Type::Str),
+            "bool->str"   => Prim::Convert(Type::Str),
+            "symbol->str" => Prim::Convert(Type::Str),
+            "list->str"   => Prim::Convert(Type::Str),
+            "str->int"    => Prim::Convert(Type::Int),
+            "char->int"   => Prim::Convert(Type::Int),
+            "float->int"  => Prim::Convert(Type::Int),
+            "int->char"   => Prim::Convert(Type::Char),
+            "int->float"  => Prim::Convert(Type::Float),
+            "str->list"   => Prim::Convert(Type::Pair),
+            "str->symbol" => Prim::Convert(Type::Symbol),
+            "vector"        => Prim::Vector,
+            "vector-ref"    => Prim::VectorRef,
+            "vector-set!"   => Prim::VectorSet,
+            "vector-length" => Prim::VectorLength,
+            "vector-slice"  => Prim::VectorSlice,
+            "make-map"      => Prim::Map,
+            "map-ref"       => Prim::MapRef,
+            "map-set!"      => Prim::MapSet,
+            "map-delete!"   => Prim::MapDelete,
+            "map-keys"      => Prim::MapKeys,
+            "map-size"      => Prim::MapSize,
+            "set-car!"      => Prim::SetCar,
+            "set-cdr!"      => Prim::SetCdr,
+            "string-length"       => Prim::Str(StrOp::Length),
+            "substring"           => Prim::Str(StrOp::Substring),
+            "string-index"        => Prim::Str(StrOp::IndexOf),
+            "string-ref"          => Prim::Str(StrOp::CharAt),
+            "string-split"        => Prim::Str(StrOp::Split),
+            "string-join"         => Prim::Str(StrOp::Join),
+            "string-upcase"       => Prim::Str(StrOp::Upcase),
+            "string-downcase"     => Prim::Str(StrOp::Downcase),
+            "string-trim"         => Prim::Str(StrOp::Trim),
+            "string-starts-with?" => Prim::Str(StrOp::StartsWith),
+            "string-ends-with?"   => Prim::Str(StrOp::EndsWith),
+            "char-alphabetic?" => Prim::Char(CharOp::IsAlphabetic),
+            "char-numeric?"    => Prim::Char(CharOp::IsNumeric),
+            "char-whitespace?" => Prim::Char(CharOp::IsWhitespace),
+            "char-upcase"      => Prim::Char(CharOp::Upcase),
+            "char-downcase"    => Prim::Char(CharOp::Downcase),
+            _ => return None,
+        })
+    }
+
+    /// Exact number of arguments, `None` for variadic primitives
+    pub fn arity(&self) -> Option<usize> {
+        match *self {
+            Prim::Not | Prim::Car | Prim::Cdr | Prim::Display | Prim::Convert(..)
+                | Prim::VectorLength | Prim::MapKeys | Prim::MapSize | Prim::Char(..) => Some(1),
+            Prim::Cons | Prim::Identical | Prim::Eqv | Prim::Equal
+                | Prim::VectorRef | Prim::MapRef | Prim::MapDelete
+                | Prim::SetCar | Prim::SetCdr | Prim::RecordIs | Prim::RecordGet(..) => Some(2),
+            Prim::VectorSet | Prim::VectorSlice | Prim::MapSet | Prim::RecordSet(..) => Some(3),
+            Prim::Str(op) => Some(op.arity()),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,18 @@
+pub mod reader;
+pub mod ir;
+pub mod compiler;
+pub mod codegen;
+
+pub use self::reader::*;
+pub use self::compiler::*;
+pub use self::codegen::*;
+
+use vm::{
+    Bin,
+    Error,
+};
+
+/// Reads, compiles and generates code for a whole source text
+pub fn compile_str(src: &str) -> Result<Bin, Error> {
+    generate(&compile_all(&read_all(src)?)?)
+}
@@ -0,0 +1,299 @@
+use vm::{
+    MemData,
+    BigInt,
+    Symbol,
+    Error,
+};
+
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Turns uLisp source text into `MemData` trees, one top-level form at a time.
+pub struct Reader<'a> {
+    chars:  Peekable<Chars<'a>>,
+    line:   usize,
+    column: usize,
+}
+
+#[inline]
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || c == '(' || c == ')' || c == '"' || c == ';' || c == '\''
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if let Some(c) = c {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        c
+    }
+
+    #[inline]
+    fn pos(&self) -> (usize, usize) {
+        (self.line, self.column)
+    }
+
+    #[inline]
+    fn eof(&self) -> Error {
+        Error::UnexpectedEof(self.line, self.column)
+    }
+
+    /// Skips whitespace and `;` comments
+    fn skip_atmosphere(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while let Some(c) = self.bump() {
+                    if c == '\n' { break; }
+                }
+            } else if c.is_whitespace() {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Reads the next datum, or `None` once the input is exhausted
+    pub fn read(&mut self) -> Option<Result<MemData, Error>> {
+        self.skip_atmosphere();
+        if self.peek().is_none() {
+            None
+        } else {
+            Some(self.read_datum())
+        }
+    }
+
+    fn read_datum(&mut self) -> Result<MemData, Error> {
+        self.skip_atmosphere();
+        let (line, col) = self.pos();
+
+        match self.peek() {
+            None => Err(self.eof()),
+            Some('(') => {
+                self.bump();
+                self.read_list_tail()
+            },
+            Some(')') =>
+                Err(Error::SyntaxError(line, col, "unexpected `)`")),
+            Some('\'') => {
+                self.bump();
+                let quoted = self.read_datum()?;
+                Ok(MemData::cons(
+                        MemData::Symbol(Symbol::intern("quote")),
+                        MemData::cons(quoted, MemData::Nil)))
+            },
+            Some('"') => {
+                self.bump();
+                self.read_string()
+            },
+            Some('#') => {
+                self.bump();
+                self.read_hash(line, col)
+            },
+            Some(_) => {
+                let tok = self.read_token();
+                if tok == "." {
+                    return Err(Error::SyntaxError(line, col, "unexpected `.` outside of a list"));
+                }
+                parse_atom(&tok, line, col)
+            },
+        }
+    }
+
+    /// Reads the elements of a list after its opening `(`
+    fn read_list_tail(&mut self) -> Result<MemData, Error> {
+        let mut items = Vec::new();
+        let mut tail = MemData::Nil;
+
+        loop {
+            self.skip_atmosphere();
+            let (line, col) = self.pos();
+            match self.peek() {
+                None => return Err(self.eof()),
+                Some(')') => {
+                    self.bump();
+                    break;
+                },
+                Some('.') if self.is_lone_dot() => {
+                    self.bump();
+                    if items.is_empty() {
+                        return Err(Error::SyntaxError(line, col, "expected a datum before `.`"));
+                    }
+                    tail = self.read_datum()?;
+
+                    self.skip_atmosphere();
+                    let (line, col) = self.pos();
+                    match self.bump() {
+                        Some(')') => break,
+                        None => return Err(self.eof()),
+                        Some(_) =>
+                            return Err(Error::SyntaxError(
+                                    line, col, "expected `)` after the tail of a dotted pair")),
+                    }
+                },
+                Some(_) => items.push(self.read_datum()?),
+            }
+        }
+
+        Ok(items.into_iter().rev().fold(tail, |cdr, car| MemData::cons(car, cdr)))
+    }
+
+    /// Whether the upcoming `.` stands alone (a dotted pair) rather than starting an atom
+    fn is_lone_dot(&self) -> bool {
+        let mut ahead = self.chars.clone();
+        ahead.next();
+        ahead.peek().is_none_or(|c| is_delimiter(*c))
+    }
+
+    fn read_token(&mut self) -> String {
+        let mut tok = String::new();
+        while let Some(c) = self.peek() {
+            if is_delimiter(c) { break; }
+            tok.push(c);
+            self.bump();
+        }
+        tok
+    }
+
+    fn read_string(&mut self) -> Result<MemData, Error> {
+        let mut s = String::new();
+        loop {
+            let (line, col) = self.pos();
+            match self.bump() {
+                None => return Err(self.eof()),
+                Some('"') => break,
+                Some('\\') => {
+                    s.push(match self.bump() {
+                        None => return Err(self.eof()),
+                        Some('n')  => '\n',
+                        Some('t')  => '\t',
+                        Some('r')  => '\r',
+                        Some('0')  => '\0',
+                        Some('\\') => '\\',
+                        Some('"')  => '"',
+                        Some(_) =>
+                            return Err(Error::SyntaxError(line, col, "unknown escape sequence")),
+                    })
+                },
+                Some(c) => s.push(c),
+            }
+        }
+        Ok(MemData::Str(s))
+    }
+
+    /// Reads `#t`, `#f` and `#\c`, `#\space` or `#\x3bb` after the leading `#`
+    fn read_hash(&mut self, line: usize, col: usize) -> Result<MemData, Error> {
+        if let Some('\\') = self.peek() {
+            self.bump();
+            // The first character is taken as-is so that `#\(` and `#\;` work
+            let c = self.bump().ok_or_else(|| self.eof())?;
+            let rest = self.read_token();
+            if rest.is_empty() {
+                return Ok(MemData::Char(c));
+            }
+            return char_name(c, &rest)
+                .map(MemData::Char)
+                .ok_or(Error::SyntaxError(line, col, "unknown character literal"));
+        }
+
+        match self.read_token().as_str() {
+            "t" | "true"  => Ok(MemData::Bool(true)),
+            "f" | "false" => Ok(MemData::Bool(false)),
+            _ => Err(Error::SyntaxError(line, col, "unknown `#` syntax")),
+        }
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<MemData, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read()
+    }
+}
+
+/// The char named by a `#\` literal longer than one char, `first` followed by `rest`
+fn char_name(first: char, rest: &str) -> Option<char> {
+    match (first, rest) {
+        ('s', "pace")   => Some(' '),
+        ('n', "ewline") => Some('\n'),
+        ('t', "ab")     => Some('\t'),
+        ('r', "eturn")  => Some('\r'),
+        ('n', "ul")     => Some('\0'),
+        ('x', hex) => u32::from_str_radix(hex, 16).ok().and_then(::std::char::from_u32),
+        _ => None,
+    }
+}
+
+fn parse_atom(tok: &str, line: usize, col: usize) -> Result<MemData, Error> {
+    let digits = tok.trim_start_matches(['+', '-']);
+    let looks_numeric = tok.len() - digits.len() <= 1
+        && !digits.is_empty()
+        && digits.chars().all(|c| c.is_ascii_digit());
+
+    if looks_numeric {
+        let tok = tok.trim_start_matches('+');
+        // Literals past the `i64` range become bignums
+        tok.parse::<i64>()
+            .map(MemData::Int)
+            .or_else(|_| tok.parse::<BigInt>().map(MemData::BigInt))
+            .map_err(|_| Error::SyntaxError(line, col, "malformed integer literal"))
+    } else if is_decimal(tok) {
+        tok.parse::<f64>()
+            .map(MemData::Float)
+            .map_err(|_| Error::SyntaxError(line, col, "malformed decimal literal"))
+    } else {
+        Ok(MemData::Symbol(Symbol::intern(tok)))
+    }
+}
+
+/// `[sign] digits [. digits] [e [sign] digits]` with a point or an exponent,
+/// and digits on at least one side of the point
+fn is_decimal(tok: &str) -> bool {
+    fn unsigned(s: &str) -> &str {
+        s.strip_prefix(|c| c == '+' || c == '-').unwrap_or(s)
+    }
+    fn all_digits(s: &str) -> bool {
+        s.chars().all(|c| c.is_ascii_digit())
+    }
+
+    let tok = unsigned(tok);
+    let (mantissa, exponent) = match tok.find(['e', 'E']) {
+        Some(i) => (&tok[..i], Some(unsigned(&tok[i + 1..]))),
+        None => (tok, None),
+    };
+    let (int, frac) = match mantissa.find('.') {
+        Some(i) => (&mantissa[..i], Some(&mantissa[i + 1..])),
+        None => (mantissa, None),
+    };
+
+    let mantissa_ok = all_digits(int)
+        && frac.is_none_or(all_digits)
+        && int.len() + frac.map_or(0, str::len) > 0;
+    let exponent_ok = exponent.is_none_or(|e| !e.is_empty() && all_digits(e));
+
+    mantissa_ok && exponent_ok && (frac.is_some() || exponent.is_some())
+}
+
+/// Reads every top-level form in `src`
+pub fn read_all(src: &str) -> Result<Vec<MemData>, Error> {
+    Reader::new(src).collect()
+}
@@ -8,71 +8,101 @@ extern crate bitflags;
 
 #[macro_use]
 mod vm;
+mod lisp;
+mod repl;
 
 #[cfg(test)]
 mod tests;
 
+use std::env;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process;
+use std::thread;
 
-#[allow(unused_imports)]
-use vm::{
-    Op,
-    OpCode,
-    Type,
-    // Instructions,
-    Bin,
-    IdentID,
-    ConstID,
-    Quantif,
-    MemData,
-    // ConstData,
-};
+const USAGE: &str = "\
+usage: ulisp                      start the repl
+       ulisp <file>               run a source (.ul), assembly (.ulasm) or bytecode (.ulc) file
+       ulisp -c <file> [-o <out>] compile a source or assembly file to bytecode
+       ulisp -d <file>            print the bytecode of a file as assembly";
 
-fn main() {
-    env_logger::init();
+fn load_bin(path: &str) -> Result<vm::Bin, String> {
+    let ext = Path::new(path).extension().and_then(|e| e.to_str());
+    if ext == Some("ulc") {
+        let f = File::open(path).map_err(|e| e.to_string())?;
+        return vm::Bin::read_from(&mut BufReader::new(f)).map_err(|e| e.to_string());
+    }
 
-    // TODO: implement lisp!
-    let mut lisp: vm::VM = vm::VM::new();
+    let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    if ext == Some("ulasm") {
+        vm::assemble(&src).map_err(|e| e.to_string())
+    } else {
+        lisp::compile_str(&src).map_err(|e| e.to_string())
+    }
+}
 
-    /*
-     *
-     *  PSS
-     *  DVR 'a' #Int(10)
-     *  DVR 'b' #Str('abc')
-     *  RRR #2
-     *  LVR 'b'
-     *  LVR 'a'
-     *  CNV #1 <int> <str>
-     *  CAT #2
-     *  DSP
-     *  PPS
-     *
-     * */
+fn run_file(path: &str) -> Result<(), String> {
+    let mut lisp = vm::VM::new();
+    let id = lisp.load(load_bin(path)?, vm::LoadOpts::DEFAULTS).map_err(|e| e.to_string())?;
+    lisp.call(&id).map(|_| ()).map_err(|e| e.to_string())
+}
 
-    // (OpCode ident n val typ [mute])
+fn compile_file(path: &str, out: &str) -> Result<(), String> {
+    let bin = load_bin(path)?;
 
-    let id = lisp.load(
-        program! {
-                    { a, b }
-                    {
-                        (#a = Int(10))
-                        (#b = Str("abc".to_owned()))
-                        (#nl = Str("\n".to_owned()))
-                    }
-                    {
-                        (PSS)
-                        (DVR a #a &)
-                        (DVR b #b &)
-                        (LVR b)
-                        (LVR a)
-                        (CNV (1) :Str)
-                        (LVR #nl)
-                        (CAT (3))
-                        (DSP)
-                        (PPS)
-                    }
+    // Written next to `out` and renamed once complete, so that a failure
+    // doesn't leave a truncated file behind
+    let tmp = format!("{}.tmp", out);
+    let written = File::create(&tmp).map_err(|e| e.to_string()).and_then(|f| {
+        let mut w = BufWriter::new(f);
+        bin.write_to(&mut w).map_err(|e| e.to_string())?;
+        w.flush().map_err(|e| e.to_string())
+    });
+    match written {
+        Ok(()) => fs::rename(&tmp, out).map_err(|e| e.to_string()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(e)
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+    }
+}
 
-    let _ = lisp.call(&id).unwrap();
+fn run(args: &[&str]) -> Result<(), String> {
+    match args {
+        [] => {
+            repl::run();
+            Ok(())
+        },
+        ["-c", src] => {
+            let out = Path::new(src).with_extension("ulc");
+            compile_file(src, &out.to_string_lossy())
+        },
+        ["-c", src, "-o", out] => compile_file(src, out),
+        ["-d", file] => load_bin(file).map(|bin| print!("{}", vm::disassemble(&bin))),
+        [file] if !file.starts_with('-') => run_file(file),
+        _ => Err(USAGE.to_owned()),
+    }
 }
 
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    // Programs may nest procedures up to vm::MAX_DEPTH deep, which takes more
+    // stack than the main thread gets
+    let r = thread::Builder::new()
+        .stack_size(vm::STACK_SIZE)
+        .spawn(move || {
+            let args: Vec<&str> = args.iter().map(|s| s.as_str()).collect();
+            run(&args)
+        })
+        .map_err(|e| e.to_string())
+        .and_then(|t| t.join().unwrap_or_else(|_| process::exit(101)));
+
+    if let Err(e) = r {
+        eprintln!("error: {}", e);
+        process::exit(1);
+    }
+}
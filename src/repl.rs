@@ -0,0 +1,109 @@
+use std::io::{
+    self,
+    BufRead,
+    Write,
+};
+
+use vm::{
+    self,
+    Error,
+    PrintMode,
+};
+use lisp;
+
+const PROMPT: &str = "ulisp> ";
+const CONTINUATION_PROMPT: &str = "  ...> ";
+
+const HELP: &str = "\
+:quit        leave the repl
+:env         list the global bindings
+:gc          free unreachable reference cycles and show heap statistics
+:heap        show heap statistics without collecting
+:dis <expr>  show the bytecode for <expr> without running it";
+
+fn prompt(s: &str) {
+    print!("{}", s);
+    let _ = io::stdout().flush();
+}
+
+/// Compiles, loads and runs one complete input, keeping its definitions around
+fn eval(lisp: &mut vm::VM, src: &str) -> Result<vm::MemData, String> {
+    let bin = lisp::compile_str(src).map_err(|e| e.to_string())?;
+    let id = lisp.load(bin, vm::LoadOpts::REUSE_VAR_STRINGS).map_err(|e| e.to_string())?;
+    let r = lisp.call(&id).map_err(|e| e.to_string());
+    // The line's definitions live on in the global scope, its entry point doesn't
+    let _ = lisp.unload(&id);
+    r
+}
+
+fn print_stats(stats: &vm::HeapStats) {
+    println!("reclaimed {} objects, {} still tracked ({} reclaimed in {} collections)",
+        stats.last_reclaimed, stats.tracked, stats.reclaimed, stats.collections);
+}
+
+fn meta_command(lisp: &mut vm::VM, line: &str) -> bool {
+    let (cmd, arg) = match line.find(char::is_whitespace) {
+        Some(i) => (&line[..i], line[i..].trim()),
+        None => (line, ""),
+    };
+
+    match cmd {
+        ":quit" | ":q" => return false,
+        ":env" => {
+            for (name, val) in lisp.bindings() {
+                // Scratch variables of the code generator aren't interesting
+                if !name.starts_with('%') {
+                    println!("{} = {}", name, val.print(PrintMode::Write));
+                }
+            }
+        },
+        ":gc" => print_stats(&lisp.gc()),
+        ":heap" => print_stats(&lisp.heap_stats()),
+        ":dis" => match lisp::compile_str(arg) {
+            Ok(bin) => print!("{}", vm::disassemble(&bin)),
+            Err(e) => eprintln!("error: {}", e),
+        },
+        _ => println!("{}", HELP),
+    }
+    true
+}
+
+/// Reads forms from stdin until `:quit` or end of input
+pub fn run() {
+    let mut lisp = vm::VM::new();
+    let stdin = io::stdin();
+    let mut input = String::new();
+
+    loop {
+        prompt(if input.is_empty() { PROMPT } else { CONTINUATION_PROMPT });
+
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => (),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                break;
+            },
+        }
+
+        if input.is_empty() && line.trim_start().starts_with(':') {
+            if !meta_command(&mut lisp, line.trim()) { break; }
+            continue;
+        }
+
+        input.push_str(&line);
+        match lisp::read_all(&input) {
+            // Keep reading until the parentheses balance
+            Err(Error::UnexpectedEof(..)) => continue,
+            Err(e) => eprintln!("error: {}", e),
+            Ok(ref forms) if forms.is_empty() => (),
+            Ok(_) => match eval(&mut lisp, &input) {
+                Ok(v) => println!("{}", v.print(PrintMode::Write)),
+                Err(e) => eprintln!("error: {}", e),
+            },
+        }
+        input.clear();
+    }
+    println!();
+}
@@ -51,7 +51,7 @@ fn prg0() {
                         (PPS)
                     }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Nil).unwrap());
 }
@@ -67,7 +67,7 @@ fn prg1() {
                     { (#a = Int(9) )}
                     { (LVR #a) }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Int(9)).unwrap())
 }
@@ -107,7 +107,7 @@ fn prg2() {
                         (ADD (2)) // #a + #c
                     }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Int(10)).unwrap());
 }
@@ -160,7 +160,7 @@ fn prg3() {
                         (IFE)
                     }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Int(321)).unwrap());
 }
@@ -209,7 +209,7 @@ fn lambda() {
                 (CLL bar)
             }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Str("Heyheyhey".to_string())).unwrap());
 }
@@ -231,7 +231,7 @@ fn multi_bin() {
                 (DVR foo #a)
             }
         },
-        vm::LoadOpts::OVERRIDE_VAR_STRINGS);
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
 
     let _ = lisp.call(&id).unwrap();
 
@@ -243,8 +243,1280 @@ fn multi_bin() {
                 (LVR foo)
             }
         },
-        vm::LoadOpts::REUSE_VAR_STRINGS);
+        vm::LoadOpts::REUSE_VAR_STRINGS).unwrap();
 
     assert!(lisp.call(&id).unwrap().eq(&MemData::Str(msg)).unwrap());
 }
 
+
+#[test]
+fn reader() {
+    use lisp::read_all;
+
+    let forms = read_all("(+ 8 (cdar (1 . (2 . 3)))) ; comment\n'x \"a\\\"b\\n\" #t #\\(").unwrap();
+    assert_eq!(forms.len(), 5);
+
+    let sym = |s: &str| MemData::Symbol(Symbol::intern(s));
+    assert_eq!(forms[0],
+               MemData::cons(sym("+"),
+               MemData::cons(MemData::Int(8),
+               MemData::cons(
+                   MemData::cons(sym("cdar"),
+                   MemData::cons(
+                       MemData::cons(MemData::Int(1),
+                       MemData::cons(MemData::Int(2), MemData::Int(3))),
+                       MemData::Nil)),
+                   MemData::Nil))));
+    assert_eq!(forms[1], MemData::cons(sym("quote"), MemData::cons(sym("x"), MemData::Nil)));
+    assert_eq!(forms[2], MemData::Str("a\"b\n".to_owned()));
+    assert_eq!(forms[3], MemData::Bool(true));
+    assert_eq!(forms[4], MemData::Char('('));
+}
+
+#[test]
+fn reader_errors() {
+    use lisp::read_all;
+
+    match read_all("(a b\n  (c . ))") {
+        Err(Error::SyntaxError(2, 8, _)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match read_all("(a\n (b c)") {
+        Err(Error::UnexpectedEof(2, 7)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match read_all(")") {
+        Err(Error::SyntaxError(1, 1, _)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn compile_ir() {
+    use lisp::{read_all, compile_all};
+    use lisp::ir::{Ir, Prim};
+
+    let forms = read_all(r#"
+        (let ((a 10) (b "abc"))
+            (display (concat b (int->str a))))
+
+        (define (cdar l)
+            (cdr (car l)))
+
+        (cond ((> 1 2) 123)
+              (else 321))
+    "#).unwrap();
+    let ir = compile_all(&forms).unwrap();
+
+    let var = |s: &str| Ir::Var(s.to_owned());
+    assert_eq!(ir[0], Ir::Do(vec![
+        Ir::DefineVar("a".to_owned(), Box::new(Ir::Const(MemData::Int(10)))),
+        Ir::DefineVar("b".to_owned(), Box::new(Ir::Const(MemData::Str("abc".to_owned())))),
+        Ir::Prim(Prim::Display, vec![
+            Ir::Prim(Prim::Concat, vec![
+                var("b"),
+                Ir::Prim(Prim::Convert(Type::Str), vec![var("a")])])]),
+    ]));
+    assert_eq!(ir[1], Ir::DefineFun("cdar".to_owned(), vec!["l".to_owned()], vec![
+        Ir::Prim(Prim::Cdr, vec![Ir::Prim(Prim::Car, vec![var("l")])])]));
+    assert_eq!(ir[2], Ir::If(
+        Box::new(Ir::Prim(Prim::Gt, vec![Ir::Const(MemData::Int(1)), Ir::Const(MemData::Int(2))])),
+        Box::new(Ir::Const(MemData::Int(123))),
+        Some(Box::new(Ir::Const(MemData::Int(321))))));
+
+    match compile_all(&read_all("(car 1 2)").unwrap()) {
+        Err(Error::CompileError(..)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+fn run(lisp: &mut vm::VM, src: &str, opts: vm::LoadOpts) -> MemData {
+    let id = lisp.load(::lisp::compile_str(src).unwrap(), opts).unwrap();
+    lisp.call(&id).unwrap()
+}
+
+#[test]
+fn codegen() {
+    init_logger();
+
+    for opts in [vm::LoadOpts::OVERRIDE_VAR_STRINGS, vm::LoadOpts::REUSE_VAR_STRINGS] {
+        let mut lisp: vm::VM = vm::VM::new();
+        let r = run(&mut lisp, r#"
+            (define (cdar l)
+                (cdr (car l)))
+            (define foo
+                (let ((x 4))
+                    (lambda (y) (+ x y))))
+            (display "cond: ")
+            (+ (foo 6)
+               (cdar (cons (cons 1 2) 3))
+               (cond ((> 1 2) (display "not true") 123)
+                     (#t (display "true\n") 321)))
+        "#, opts);
+        assert!(r.eq(&MemData::Int(333)).unwrap());
+    }
+}
+
+#[test]
+fn capture() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let id = lisp.load(
+        program! {
+            { x, f, g }
+            {
+                (#a = Int(7))
+                (#b = Int(8))
+            }
+            {
+                (DVR x #a &)
+                    (REC (1))
+                        (LVR x)
+                    (PRC (1))
+                (CAP x)
+                (DVR f &)
+
+                // `f` keeps its copy, `g` sees the whole scope
+                (DVR x #b &)
+                    (REC (1))
+                        (LVR x)
+                (DFN (1) g &)
+                (CLL f)
+                (CLL g)
+                (ADD (2))
+                (LVR x)
+                (RRR (1))
+            }
+        },
+        vm::LoadOpts::OVERRIDE_VAR_STRINGS).unwrap();
+
+    assert_eq!(*lisp.call(&id).unwrap().deref(), MemData::Int(15));
+
+    // Captures of one closure share a single private frame on top of the global one
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    match *run(&mut lisp, "(let ((a 1) (b 2) (c 3)) (lambda () (+ a b c)))", reuse).deref() {
+        MemData::Lambda(_, ref env) => assert_eq!(env.depth(), 2),
+        ref v => panic!("unexpected result: {:?}", v),
+    }
+}
+
+#[test]
+fn closures() {
+    init_logger();
+
+    let src = r#"
+        (define (make-adder n) (lambda (x) (+ x n)))
+        (define add2 (make-adder 2))
+        (define (count-down n) (if (= n 0) 0 (count-down (- n 1))))
+        (let ((k 10))
+            (define (loop i acc) (if (> i k) acc (loop (+ i 1) (+ acc i))))
+            (+ (add2 1) (count-down 5) (loop 1 0)))
+    "#;
+    let text = vm::disassemble(&::lisp::compile_str(src).unwrap());
+    assert!(text.contains("CAP 'n'"), "{}", text);
+    // `loop` refers to itself, so it can't be captured before it is defined
+    assert!(text.contains("DFN 'loop'"), "{}", text);
+    assert!(!text.contains("LMB"), "{}", text);
+
+    let mut lisp: vm::VM = vm::VM::new();
+    assert_eq!(*run(&mut lisp, src, vm::LoadOpts::REUSE_VAR_STRINGS).deref(), MemData::Int(58));
+}
+
+#[test]
+fn integer_arithmetic() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    assert_eq!(*run(&mut lisp, "(- 3 5)", reuse).deref(), MemData::Int(-2));
+    assert_eq!(*run(&mut lisp, "(define (cdar l) (cdr (car l))) (- 7 (cdar (cons (cons 1 2) 3)))", reuse).deref(),
+               MemData::Int(5));
+    assert_eq!(*run(&mut lisp, "(* -3 (/ -9 2))", reuse).deref(), MemData::Int(12));
+    // A single argument is negated
+    assert_eq!(*run(&mut lisp, "(- 5)", reuse).deref(), MemData::Int(-5));
+    assert_eq!(*run(&mut lisp, "(- (- 3 5))", reuse).deref(), MemData::Int(2));
+    assert_eq!(*run(&mut lisp, "(- 1.5)", reuse).deref(), MemData::Float(-1.5));
+    assert_eq!(*run(&mut lisp, "(- -9223372036854775808)", reuse).deref(),
+               MemData::BigInt("9223372036854775808".parse().unwrap()));
+
+    let fails = vec![
+        ("(/ 1 (- 2 2))", "division by zero"),
+        ("(/ 99999999999999999999 (- 2 2))", "division by zero"),
+    ];
+    for (src, msg) in fails {
+        let id = lisp.load(::lisp::compile_str(src).unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            Err(e) => assert_eq!(e.error.to_string(), msg),
+            Ok(v) => panic!("{} returned {:?}", src, v),
+        }
+    }
+
+    let bin = vm::assemble("LVR #Int(-9223372036854775808)").unwrap();
+    assert_eq!(bin.consts(), &[MemData::Int(i64::MIN)]);
+}
+
+#[test]
+fn bignums() {
+    init_logger();
+
+    let big = |s: &str| MemData::BigInt(s.parse().unwrap());
+    assert_eq!(::lisp::read_all("9223372036854775807 9223372036854775808 -9223372036854775809").unwrap(),
+               vec![MemData::Int(i64::MAX),
+                    big("9223372036854775808"),
+                    big("-9223372036854775809")]);
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(* 4611686018427387904 2)", big("9223372036854775808")),
+        ("(- -9223372036854775807 2)", big("-9223372036854775809")),
+        ("(/ -9223372036854775808 -1)", big("9223372036854775808")),
+        // Results that fit again are demoted
+        ("(- (* 4611686018427387904 4) 9223372036854775808 9223372036854775808 1)", MemData::Int(-1)),
+        ("(/ (* 123456789012 987654321098 555555555555) 987654321098 555555555555)", MemData::Int(123456789012)),
+        ("(define (fact n) (if (= n 0) 1 (* n (fact (- n 1))))) (fact 30)", big("265252859812191058636308480000000")),
+        ("(- (fact 25) (fact 25) 1)", MemData::Int(-1)),
+        ("(> (fact 21) (fact 20))", MemData::Bool(true)),
+        ("(< (- 0 (fact 21)) -9223372036854775808)", MemData::Bool(true)),
+        ("(= (fact 22) (* 22 (fact 21)))", MemData::Bool(true)),
+        ("(> (fact 21) 1e19)", MemData::Bool(true)),
+        ("(+ (fact 25) 0.5)", MemData::Float(15511210043330985984000000.5)),
+        ("(int->str (fact 25))", MemData::Str("15511210043330985984000000".to_owned())),
+        ("(/ (fact 28) (fact 26))", MemData::Int(756)),
+        ("(/ (fact 30) (- 0 (fact 22)))", MemData::Int(-235989936000)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    // Division a limb at a time: q * b + r == a with r smaller than b, for
+    // divisors with the top limb full, nearly empty and in between
+    let mut seed = 0x2545f4914f6cdd1du64;
+    let mut limbs = |n: usize, top: u32| {
+        let mut l: Vec<u32> = (0..n).map(|_| {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed as u32
+        }).collect();
+        l.push(top);
+        l
+    };
+    for &(an, bn) in &[(2, 1), (4, 2), (7, 3), (12, 5), (20, 19), (30, 12)] {
+        for &top in &[1, 0x7fff, 0x8000_0000, u32::MAX] {
+            let a = BigInt::from_parts(false, limbs(an, u32::MAX));
+            let b = BigInt::from_parts(top == 1, limbs(bn, top));
+            let (q, r) = a.div_rem(&b).unwrap();
+            assert_eq!(&(&q * &b) + &r, a);
+            assert!(!r.is_negative() && r < BigInt::from_parts(false, b.limbs().to_vec()));
+        }
+    }
+    // A quotient limb guessed one too large, which has to add the divisor back
+    let a = BigInt::from_parts(false, vec![3, 0, 0x8000_0000]);
+    let b = BigInt::from_parts(false, vec![1, 0, 0x2000_0000]);
+    let (q, r) = a.div_rem(&b).unwrap();
+    assert_eq!((q.limbs(), r.limbs()), (&[3][..], &[0, 0, 0x2000_0000][..]));
+
+    let bin = vm::assemble("LVR #BigInt(-340282366920938463463374607431768211456)").unwrap();
+    let mut bytes = Vec::new();
+    bin.write_to(&mut bytes).unwrap();
+    let read = vm::Bin::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(read.consts(), &[big("-340282366920938463463374607431768211456")]);
+    assert_eq!(vm::disassemble(&read), "LVR #BigInt(-340282366920938463463374607431768211456)\n");
+
+    // Bignums that fit are demoted when assembled or read, like arithmetic results
+    let bin = vm::assemble("LVR #BigInt(-5)").unwrap();
+    assert_eq!(bin.consts(), &[MemData::Int(-5)]);
+    let bin = Bin::new(
+        vec![Op::new(OpCode::LVR, None, None, Some(0), None, false)].into(),
+        vec![],
+        Default::default(),
+        vec![MemData::BigInt(BigInt::from_i64(5))]);
+    let mut bytes = Vec::new();
+    bin.write_to(&mut bytes).unwrap();
+    let read = vm::Bin::read_from(&mut &bytes[..]).unwrap();
+    assert_eq!(read.consts(), &[MemData::Int(5)]);
+}
+
+#[test]
+fn floats() {
+    init_logger();
+
+    let forms = ::lisp::read_all("1.5 -2. .5 +1e3 -1.5E-2 1e 1.2.3 e5").unwrap();
+    assert_eq!(forms, vec![
+        MemData::Float(1.5),
+        MemData::Float(-2.0),
+        MemData::Float(0.5),
+        MemData::Float(1000.0),
+        MemData::Float(-0.015),
+        MemData::Symbol(Symbol::intern("1e")),
+        MemData::Symbol(Symbol::intern("1.2.3")),
+        MemData::Symbol(Symbol::intern("e5")),
+    ]);
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(/ (+ 1 2 3 4) 4.0)", MemData::Float(2.5)),
+        ("(* 2 1.5)", MemData::Float(3.0)),
+        ("(- 1 0.25 0.25)", MemData::Float(0.5)),
+        ("(/ 1 0.0)", MemData::Float(f64::INFINITY)),
+        ("(> 2.5 2)", MemData::Bool(true)),
+        ("(< 2 1.5)", MemData::Bool(false)),
+        ("(= 1 1.0)", MemData::Bool(true)),
+        ("(define x 0) (= x 0)", MemData::Bool(true)),
+        ("(int->str 2.5)", MemData::Str("2.5".to_owned())),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let bin = vm::assemble("LVR #Float(-1.5e-3)\nLVR #Float(inf)").unwrap();
+    let mut buf = Vec::new();
+    bin.write_to(&mut buf).unwrap();
+    let read = Bin::read_from(&mut buf.as_slice()).unwrap();
+    assert_eq!(read.consts(), &[MemData::Float(-0.0015), MemData::Float(f64::INFINITY)]);
+    assert_eq!(vm::disassemble(&read), "LVR #Float(-0.0015)\nLVR #Float(inf)\n");
+}
+
+#[test]
+fn boolean_ops() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(not #f)", MemData::Bool(true)),
+        ("(not 0)", MemData::Bool(false)),
+        ("(not \"\")", MemData::Bool(false)),
+        ("(and)", MemData::Bool(true)),
+        ("(or)", MemData::Bool(false)),
+        ("(and 1 2)", MemData::Int(2)),
+        ("(or #f 3)", MemData::Int(3)),
+        // The remaining arguments must not be evaluated
+        ("(and #f (car 1))", MemData::Bool(false)),
+        ("(or 1 (car 1))", MemData::Int(1)),
+        ("(define x 1) (or #f (and x #t) 2) (not x)", MemData::Bool(false)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+}
+
+#[test]
+fn codegen_multi_bin() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "(define (add a b) (+ a b)) (define x 5)", reuse);
+    run(&mut lisp, "(define y (add x 2))", reuse);
+    assert!(run(&mut lisp, "(add y x)", reuse).eq(&MemData::Int(12)).unwrap());
+    assert!(run(&mut lisp, "((lambda (a) (* a a)) y)", reuse).eq(&MemData::Int(49)).unwrap());
+}
+
+#[test]
+fn call_after_runtime_error() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "(define x 5)", reuse);
+
+    let id = lisp.load(::lisp::compile_str("(let ((y 1)) (car y))").unwrap(), reuse).unwrap();
+    assert!(lisp.call(&id).is_err());
+
+    assert!(run(&mut lisp, "(+ x 1)", reuse).eq(&MemData::Int(6)).unwrap());
+    assert!(lisp.bindings().iter().any(|(s, _)| s == "x"));
+}
+
+#[test]
+fn recursion_limit() {
+    init_logger();
+
+    // Getting to the limit takes as much stack as the binary gives its VM thread
+    let t = ::std::thread::Builder::new().stack_size(vm::STACK_SIZE).spawn(|| {
+        let mut lisp: vm::VM = vm::VM::new();
+        let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+        run(&mut lisp, "(define (count n) (if (= n 0) 0 (+ 1 (count (- n 1)))))", reuse);
+        assert!(run(&mut lisp, "(count 500)", reuse).eq(&MemData::Int(500)).unwrap());
+
+        let id = lisp.load(::lisp::compile_str("(count 5000)").unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            Err(ref e) => assert!(e.to_string().ends_with(
+                    "recursion limit exceeded: more than 2000 nested procedures")),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        assert!(run(&mut lisp, "(count 10)", reuse).eq(&MemData::Int(10)).unwrap());
+    }).unwrap();
+    t.join().unwrap();
+}
+
+#[test]
+fn unload() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "(define x 0)", reuse);
+
+    // Like the repl: every line gets the same id back and reuses the constants
+    let mut ids = Vec::new();
+    for _ in 0..100 {
+        let id = lisp.load(::lisp::compile_str("(set! x (+ x 1))").unwrap(), reuse).unwrap();
+        lisp.call(&id).unwrap();
+        lisp.unload(&id).unwrap();
+        ids.push(id);
+    }
+    assert!(ids.iter().all(|id| *id == ids[0]), "{:?}", ids);
+    assert!(run(&mut lisp, "x", reuse).eq(&MemData::Int(100)).unwrap());
+
+    // Running out of constant ids is an error, not an overflow
+    let mut r = Ok(0);
+    for i in 0..65 {
+        let bin = Bin::new(
+            vec![Op::new(OpCode::LVR, None, None, Some(0), None, false)].into(),
+            vec![],
+            Default::default(),
+            (0..1024).map(|j| MemData::Str(format!("{}-{}", i, j))).collect());
+        r = lisp.load(bin, reuse);
+        if r.is_err() { break; }
+    }
+    match r {
+        Err(Error::ConstantsExhausted) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+/// Length of the data section of a bin file, which comes last
+fn data_len(file: &[u8]) -> usize {
+    u32::from_le_bytes([file[16], file[17], file[18], file[19]]) as usize
+}
+
+#[test]
+fn bin_file_roundtrip() {
+    init_logger();
+
+    let bin = program! {
+        { foo }
+        {
+            (#a = Str("héllo".to_owned()))
+            (#b = Pair(Cons::new(MemData::Int(1), MemData::Symbol(Symbol::intern("x")))))
+        }
+        {
+            (DVR foo #b &)
+            (LVR foo)
+            (CAR (1))
+            (CNV (1) :Str)
+            (LVR #a)
+            (CAT (2))
+        }
+    };
+
+    let mut buf = Vec::new();
+    bin.write_to(&mut buf).unwrap();
+    let read = Bin::read_from(&mut buf.as_slice()).unwrap();
+
+    assert_eq!(read.insts(), bin.insts());
+    assert_eq!(read.idents(), bin.idents());
+    assert_eq!(read.var_strings(), bin.var_strings());
+    assert_eq!(read.consts(), bin.consts());
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let id = lisp.load(read, vm::LoadOpts::DEFAULTS).unwrap();
+    assert!(lisp.call(&id).unwrap().eq(&MemData::Str("1héllo".to_owned())).unwrap());
+
+    // Section sizes beyond the end of the file, huge or not, are rejected
+    let mut huge = buf.clone();
+    huge[16..20].copy_from_slice(&[0xff; 4]);
+    for bad in &[huge, buf[..buf.len() - 1].to_vec()] {
+        match Bin::read_from(&mut bad.as_slice()) {
+            Err(Error::BadBin(_)) => (),
+            r => panic!("unexpected result: {:?}", r.map(|_| ())),
+        }
+    }
+
+    // Long lists are written in a loop, and dropped in one too
+    let long = Bin::new(
+        vec![Op::new(OpCode::LVR, None, None, Some(0), None, false)].into(),
+        vec![],
+        Default::default(),
+        vec![MemData::list((0..200000).map(MemData::Int).collect())]);
+    let mut bytes = Vec::new();
+    long.write_to(&mut bytes).unwrap();
+    // A pair, then an Int car, for each item and a Nil at the end
+    assert_eq!(data_len(&bytes), 200000 * (1 + 1 + 8) + 1);
+
+    // They are read in a loop as well, but deeply nested cars are rejected
+    let read = Bin::read_from(&mut bytes.as_slice()).unwrap();
+    let items = read.consts()[0].list_items().unwrap();
+    assert_eq!((items.len(), &items[199999]), (200000, &MemData::Int(199999)));
+
+    let mut deep = bytes[..bytes.len() - data_len(&bytes)].to_vec();
+    deep[16..20].copy_from_slice(&(2 * 20000 + 1u32).to_le_bytes());
+    deep.extend(vec![Type::Pair as u8; 20000]);
+    deep.extend(vec![Type::Nil as u8; 20001]);
+    match Bin::read_from(&mut deep.as_slice()) {
+        Err(Error::BadBin(_)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+
+    // Files from another version are rejected
+    buf[4] = buf[4].wrapping_add(1);
+    match Bin::read_from(&mut buf.as_slice()) {
+        Err(Error::UnsupportedBinVersion(_)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+    match Bin::read_from(&mut &b"not a bin file at all"[..]) {
+        Err(Error::BadBin(_)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn op_encoding_roundtrip() {
+    use vm::encode::OpFlags;
+
+    let operands = [
+        (None, None, None, None, false),
+        (Some(7), None, None, None, true),
+        (None, Some(0x3ffff), None, None, false),
+        (Some(65535), Some(3), None, None, false),
+        (Some(2), None, Some(65535), None, true),
+        (None, Some(1), None, Some(Type::Str), false),
+        (Some(9), Some(4), Some(12), Some(Type::Nil), true),
+    ];
+
+    for opcode in vm::OPCODES {
+        for &(ident, n, val, typ, mute) in operands.iter() {
+            let op = Op::new(opcode.clone(), ident, n, val, typ, mute);
+            let mut words = Vec::new();
+            op.encode(&mut words).unwrap();
+            assert_eq!(words[0] >> 26, opcode.clone() as u32);
+            assert_eq!(Op::decode(&words).unwrap(), (op, words.len()));
+        }
+    }
+
+    let mut words = Vec::new();
+    match Op::new(OpCode::LMB, None, Some(1 << 18), None, None, false).encode(&mut words) {
+        Err(Error::EncodingOverflow("n", _)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // LVR with an extension word holding a const id wider than ConstID
+    let lvr = (OpCode::LVR as u32) << 26 | (OpFlags::EXPANSION | OpFlags::CONST).bits() as u32;
+    match Op::decode(&[lvr, 1 << 16]) {
+        Err(Error::EncodingOverflow("const", _)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+    match Op::decode(&[lvr]) {
+        Err(Error::BadEncoding(_)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn assembler() {
+    init_logger();
+
+    let src = "\
+; spec.md style quantifiers and lower case types are accepted
+PSS
+DVR 'a' #Int(10) &
+DVR b #Str(\"x\\\"y\\n\") &
+REC (2)
+    LVR #Pair(Str(\"!\"), Char('\\''))
+    CAR (1)
+LMB (2)
+DVR @7 &
+LVR 'a'
+CNV #1 <int> <str>
+LVR b
+CLL @7
+CAT 3
+PPS
+";
+    let bin = vm::assemble(src).unwrap();
+    assert_eq!(bin.insts().len(), 14);
+    assert_eq!(bin.consts().len(), 3);
+    assert_eq!(bin.idents().len(), 3);
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let id = lisp.load(bin, vm::LoadOpts::DEFAULTS).unwrap();
+    assert!(lisp.call(&id).unwrap().eq(&MemData::Str("10x\"y\n!".to_owned())).unwrap());
+
+    // Ops inside constants are relocated along with the rest of the bin
+    let bin = vm::assemble("\
+DVR f #Proc(LVR #Str(\"in proc\"), LVR 'y', CAT 2) &
+DVR y #Str(\"!\") &
+CLL f
+").unwrap();
+    let shift = vm::assemble("DVR z #Str(\"shifted\") &\nLVR #Int(2)").unwrap();
+    let id = lisp.load(shift, vm::LoadOpts::DEFAULTS).unwrap();
+    lisp.call(&id).unwrap();
+    let id = lisp.load(bin, vm::LoadOpts::DEFAULTS).unwrap();
+    assert!(lisp.call(&id).unwrap().eq(&MemData::Str("in proc!".to_owned())).unwrap());
+
+    // Long list constants are relocated and disassembled in a loop
+    let items: Vec<String> = (0..200000).map(|i| i.to_string()).collect();
+    let src = format!("(define x '({})) (car (cdr x))", items.join(" "));
+    let bin = ::lisp::compile_str(&src).unwrap();
+    let text = vm::disassemble(&bin);
+    assert!(text.contains("#Pair(Int(0), Pair(Int(1), Pair(Int(2), "));
+    assert!(text.contains(&format!("Pair(Int(199999), Nil{}", ")".repeat(200000))));
+    let id = lisp.load(bin, vm::LoadOpts::DEFAULTS).unwrap();
+    assert!(lisp.call(&id).unwrap().eq(&MemData::Int(1)).unwrap());
+
+    // Repeated constants are assembled once
+    let bin = vm::assemble("LVR #Int(1)\nLVR #Str(\"a\")\nLVR #Int(1)\nLVR #Str(\"a\")").unwrap();
+    assert_eq!(bin.consts(), &[MemData::Int(1), MemData::Str("a".to_owned())]);
+
+    match vm::assemble("PSS\nDVR 'a' #Int(10 &\nPPS") {
+        Err(Error::SyntaxError(2, _, _)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+    match vm::assemble("FOO 'a'") {
+        Err(Error::SyntaxError(1, _, _)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn disassembler_roundtrip() {
+    let bin = ::lisp::compile_str("
+        (define (f x) (if (> x 2) \"big\" (cons x #\\a)))
+        (display (f 3))
+        (lambda (y) (concat y \"\\t\"))
+    ").unwrap();
+
+    let text = vm::disassemble(&bin);
+    assert!(text.contains("\n    "), "recorded bodies are indented:\n{}", text);
+
+    let again = vm::assemble(&text).unwrap();
+    assert_eq!(again.insts().len(), bin.insts().len());
+    assert_eq!(again.consts(), bin.consts());
+    assert_eq!(vm::disassemble(&again), text);
+}
+
+fn problems(src: &str) -> Vec<(usize, &'static str)> {
+    match vm::verify(&vm::assemble(src).unwrap()) {
+        Ok(()) => Vec::new(),
+        Err(ps) => ps.into_iter().map(|p| (p.instruction_num, p.problem)).collect(),
+    }
+}
+
+#[test]
+fn verifier() {
+    init_logger();
+
+    // Bodies may take their arguments from the caller's register
+    assert_eq!(problems("REC 3\n    DVR 'x' &\n    LVR 'x'\n    CNS\nLMB 3\nDVR 'f'"), vec![]);
+    assert_eq!(problems("LVR #Int(1)\nCNS"), vec![(1, "pops more values than the register holds")]);
+    assert_eq!(problems("REC 1\n    CNS\nCLL 1"), vec![(2, "pops more values than the register holds")]);
+    assert_eq!(problems("LVR #Nil\nREC 2\n    LVR #Nil"), vec![(1, "REC records past the end of the procedure")]);
+    assert_eq!(problems("PSS\nREC 1\n    PPS\nPRC 1\nPPS\nPPS"),
+               vec![(2, "PPS without a matching PSS"), (5, "PPS without a matching PSS")]);
+    assert_eq!(problems("PSS\nLVR #Nil"), vec![(0, "PSS without a matching PPS")]);
+
+    let bin = Bin::new(
+        vec![
+            Op::new(OpCode::LVR, None, None, Some(3), None, false),
+            Op::new(OpCode::DVR, Some(9), None, None, None, true),
+        ].into(),
+        vec![],
+        Default::default(),
+        vec![]);
+    let mut lisp: vm::VM = vm::VM::new();
+    match lisp.load(bin, vm::LoadOpts::DEFAULTS) {
+        Err(Error::VerificationFailed(ps)) => {
+            let ps: Vec<&str> = ps.iter().map(|p| p.problem).collect();
+            assert_eq!(ps, vec!["constant is not part of the bin", "identifier is not declared by the bin"]);
+        },
+        r => panic!("unexpected result: {:?}", r),
+    }
+
+    // Ops inside constants are verified too, they'd run all the same
+    let bin = vm::assemble("DVR f #Proc(LVR #Str(\"a\"), STR) &\nLVR #Pair(Int(1), Inst(LMB))\nCLL f").unwrap();
+    match vm::verify(&bin) {
+        Err(ps) => {
+            let ps: Vec<(bool, usize, &str)> = ps.iter()
+                .map(|p| (p.constant.is_some(), p.instruction_num, p.problem))
+                .collect();
+            assert_eq!(ps, vec![
+                (true, 1, "STR without a valid string operation"),
+                (true, 0, "missing quantifier"),
+            ]);
+        },
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn quote() {
+    init_logger();
+
+    let sym = |s: &str| MemData::Symbol(Symbol::intern(s));
+    let name = String::from("foo");
+    assert_eq!(Symbol::intern("foo"), Symbol::intern(&name));
+    assert!(Symbol::intern("foo") != Symbol::intern("bar"));
+
+    let bin = ::lisp::compile_str("(cdr '(a b c))").unwrap();
+    assert!(bin.consts().contains(&MemData::cons(sym("a"),
+                                  MemData::cons(sym("b"),
+                                  MemData::cons(sym("c"), MemData::Nil)))));
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("'foo", sym("foo")),
+        ("(quote 12)", MemData::Int(12)),
+        ("(car (cdr '(a b c)))", sym("b")),
+        ("(cdr '(1 . \"x\"))", MemData::Str("x".to_owned())),
+        ("(= 'foo (car '(foo bar)))", MemData::Bool(true)),
+        ("(= 'foo 'bar)", MemData::Bool(false)),
+        ("(car ''x)", sym("quote")),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    match ::lisp::compile_str("(quote a b)") {
+        Err(Error::CompileError("quote takes exactly one datum", _)) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn vectors() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(define v (vector 1 2 3 4)) (vector-length v)", MemData::Int(4)),
+        ("(vector-ref v 2)", MemData::Int(3)),
+        // Both names refer to the same vector
+        ("(define w v) (vector-set! w 2 5) (vector-ref v 2)", MemData::Int(5)),
+        ("(define (set-first! x) (vector-set! x 0 'a)) (set-first! v) (vector-ref w 0)",
+         MemData::Symbol(Symbol::intern("a"))),
+        ("(vector-length (vector-slice v 1 4))", MemData::Int(3)),
+        ("(vector-length (vector-slice v 4 4))", MemData::Int(0)),
+        // Slices are copies
+        ("(define s (vector-slice v 0 2)) (vector-set! s 1 0) (vector-ref v 1)", MemData::Int(2)),
+        ("(vector-length (vector))", MemData::Int(0)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let fails = vec![
+        ("(vector-ref v 4)", "index 4 is out of range for length 4"),
+        ("(vector-set! v -1 0)", "index -1 is out of range for length 4"),
+        ("(vector-slice v 0 5)", "index 5 is out of range for length 4"),
+        ("(vector-slice v 3 1)", "range start 3 is past its end 1"),
+        ("(vector-ref (vector) 0)", "index 0 is out of range for length 0"),
+    ];
+    for (src, msg) in fails {
+        let id = lisp.load(::lisp::compile_str(src).unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            Err(e) => assert_eq!(e.error.to_string(), msg),
+            Ok(v) => panic!("{} returned {:?}", src, v),
+        }
+    }
+}
+
+#[test]
+fn maps() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(define m (make-map 'a 1 \"b\" 2 #t 3)) (map-size m)", MemData::Int(3)),
+        ("(map-ref m 'a)", MemData::Int(1)),
+        ("(map-ref m \"b\")", MemData::Int(2)),
+        ("(map-ref m 'b)", MemData::Nil),
+        ("(define (count! m k) (map-set! m k (+ 1 (map-ref m k)))) (count! m 'a) (count! m 'a) (map-ref m 'a)",
+         MemData::Int(3)),
+        ("(map-set! m '() 4) (map-delete! m #t) (map-delete! m 'c) (map-size m)", MemData::Int(3)),
+        ("(map-ref m '())", MemData::Int(4)),
+        ("(map-size (make-map))", MemData::Int(0)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let keys = run(&mut lisp, "(map-keys m)", reuse).list_items().unwrap();
+    let mut keys: Vec<String> = keys.iter().map(|k| format!("{:?}", k)).collect();
+    keys.sort();
+    assert_eq!(keys, vec!["Nil", "Str(\"b\")", "Symbol(\"a\")"]);
+
+    let id = lisp.load(::lisp::compile_str("(map-set! m (lambda () 1) 2)").unwrap(), reuse).unwrap();
+    match lisp.call(&id) {
+        Err(e) => assert_eq!(e.error.to_string(), "values of type `Lambda` cannot be used as map keys"),
+        Ok(v) => panic!("returned {:?}", v),
+    }
+    assert!(::lisp::compile_str("(make-map 'a)").is_err());
+
+    // The same operations from Rust
+    let m = MemData::map(vec![(MemData::Char('x'), MemData::Int(1))]).unwrap();
+    assert_eq!(m.map_insert(&MemData::Int(7), MemData::Bool(true)).unwrap(), None);
+    assert_eq!(m.map_get(&MemData::Char('x')).unwrap(), Some(MemData::Int(1)));
+    assert_eq!(m.map_remove(&MemData::Char('x')).unwrap(), Some(MemData::Int(1)));
+    assert_eq!(m.map_keys().unwrap(), vec![MemData::Int(7)]);
+    assert_eq!(m.map_len().unwrap(), 1);
+    match m.map_get(&MemData::Float(1.0)) {
+        Err(Error::UnhashableKey(Type::Float)) => (),
+        r => panic!("unexpected result: {:?}", r),
+    }
+}
+
+#[test]
+fn mutable_pairs() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(define l (cons 1 (cons 2 (cons 3 '())))) (define tail (cdr l)) (set-car! tail 20) (car (cdr l))",
+         MemData::Int(20)),
+        ("(define (second! p v) (set-car! (cdr p) v)) (second! l 200) (car tail)", MemData::Int(200)),
+        ("(set-cdr! (cdr tail) (cons 4 '())) (car (cdr (cdr (cdr l))))", MemData::Int(4)),
+        ("(define m l) (set-car! m 'x) (car l)", MemData::Symbol(Symbol::intern("x"))),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    // Taking the cdr shares the tail instead of copying it
+    let l = MemData::list(vec![MemData::Int(1), MemData::Int(2)]);
+    let (head, tail) = match l {
+        MemData::Pair(ref c) => (c.clone(), c.cdr()),
+        _ => unreachable!(),
+    };
+    match (head.cdr(), tail) {
+        (MemData::Pair(a), MemData::Pair(b)) => assert!(a.ptr_eq(&b)),
+        r => panic!("unexpected values: {:?}", r),
+    }
+
+    let id = lisp.load(::lisp::compile_str("(set-car! '() 1)").unwrap(), reuse).unwrap();
+    match lisp.call(&id) {
+        Err(e) => assert_eq!(e.error.to_string(), "expected type `Pair` but found `Nil`"),
+        Ok(v) => panic!("returned {:?}", v),
+    }
+}
+
+#[test]
+fn set() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(define x 1) (set! x (+ x 1)) x", MemData::Int(2)),
+        // Assigns the global from inside a function
+        ("(define (bump!) (set! x (* x 10))) (bump!) (bump!) x", MemData::Int(200)),
+        ("(define (make-counter)
+            (define n 0)
+            (lambda () (set! n (+ n 1)) n))
+          (define c (make-counter))
+          (define d (make-counter))
+          (c) (c) (d)
+          (c)", MemData::Int(3)),
+        // Closures over the same variable see each other's assignments
+        ("(define (make-account total)
+            (cons (lambda (v) (set! total (+ total v)))
+                  (lambda () total)))
+          (define acc (make-account 10))
+          ((car acc) 5)
+          ((cdr acc))", MemData::Int(15)),
+        ("(define (sum-to n)
+            (define total 0)
+            (define (loop i) (if (> i n) total (do (set! total (+ total i)) (loop (+ i 1)))))
+            (loop 1))
+          (sum-to 10)", MemData::Int(55)),
+        // An inner define shadows, set! updates the nearest binding
+        ("(define y 1) (let ((y 2)) (set! y 3)) y", MemData::Int(1)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let bin = ::lisp::compile_str("(set! undefined-var 1)").unwrap();
+    let id = lisp.load(bin, reuse).unwrap();
+    match lisp.call(&id) {
+        Err(RuntimeError { error: Error::VariableNotFound(..), .. }) => (),
+        r => panic!("unexpected result: {:?}", r.map(|_| ())),
+    }
+}
+
+#[test]
+fn conversions() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let chars = |s: &str| MemData::list(s.chars().map(MemData::Char).collect());
+    let cases = vec![
+        ("(str->int \" -42 \")", MemData::Int(-42)),
+        ("(str->int \"123456789012345678901234567890\")", MemData::BigInt("123456789012345678901234567890".parse().unwrap())),
+        ("(int->char 97)", MemData::Char('a')),
+        ("(char->int #\\a)", MemData::Int(97)),
+        ("(char->str #\\a)", MemData::Str("a".to_owned())),
+        ("(bool->str (> 2 1))", MemData::Str("#t".to_owned())),
+        ("(symbol->str 'foo)", MemData::Str("foo".to_owned())),
+        ("(str->list \"hi\")", chars("hi")),
+        ("(str->list \"\")", MemData::Nil),
+        ("(list->str (cons #\\o (cons #\\k '())))", MemData::Str("ok".to_owned())),
+        ("(list->str (str->list \"round trip\"))", MemData::Str("round trip".to_owned())),
+        ("(float->int -2.75)", MemData::Int(-2)),
+        ("(int->float 3)", MemData::Float(3.0)),
+        // Identity
+        ("(int->str \"already\")", MemData::Str("already".to_owned())),
+        ("(str->int 5)", MemData::Int(5)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let fails = vec![
+        ("(str->int \"12a\")", "cannot parse \"12a\" as `Int`"),
+        ("(int->char 55296)", "illegal conversion target: from `Int` to `Char`"),
+        ("(list->str (cons 1 '()))", "expected type `Char` but found `Int`"),
+        ("(int->str (lambda () 1))", "illegal conversion target: from `Lambda` to `Str`"),
+        ("(str->int #t)", "illegal conversion target: from `Bool` to `Int`"),
+    ];
+    for (src, msg) in fails {
+        let id = lisp.load(::lisp::compile_str(src).unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            Err(e) => assert_eq!(e.error.to_string(), msg),
+            Ok(v) => panic!("{} returned {:?}", src, v),
+        }
+    }
+}
+
+#[test]
+fn strings() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let strs = |v: &[&str]| MemData::list(v.iter().map(|s| MemData::Str((*s).to_owned())).collect());
+    let cases = vec![
+        ("(string-length \"héllo\")", MemData::Int(5)),
+        ("(substring \"héllo\" 1 3)", MemData::Str("él".to_owned())),
+        ("(substring \"abc\" 3 3)", MemData::Str("".to_owned())),
+        ("(string-index \"héllo\" \"llo\")", MemData::Int(2)),
+        ("(string-index \"abc\" \"z\")", MemData::Bool(false)),
+        ("(string-ref \"héllo\" 1)", MemData::Char('é')),
+        ("(string-split \"a,b,,c\" \",\")", strs(&["a", "b", "", "c"])),
+        ("(string-split \"hé\" \"\")", strs(&["h", "é"])),
+        ("(string-join (string-split \"a b c\" \" \") \"-\")", MemData::Str("a-b-c".to_owned())),
+        ("(string-join '() \",\")", MemData::Str("".to_owned())),
+        ("(string-upcase \"straße\")", MemData::Str("STRASSE".to_owned())),
+        ("(string-downcase \"ÉTÉ\")", MemData::Str("été".to_owned())),
+        ("(string-trim \"  x y \n\")", MemData::Str("x y".to_owned())),
+        ("(string-starts-with? \"prefix\" \"pre\")", MemData::Bool(true)),
+        ("(string-ends-with? \"prefix\" \"pre\")", MemData::Bool(false)),
+        ("(< \"apple\" \"banana\")", MemData::Bool(true)),
+        ("(= \"same\" (concat \"sa\" \"me\"))", MemData::Bool(true)),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let fails = vec![
+        ("(substring \"abc\" 2 1)", "range start 2 is past its end 1"),
+        ("(string-ref \"abc\" 3)", "index 3 is out of range for length 3"),
+        ("(string-length 5)", "expected type `Str` but found `Int`"),
+        ("(string-join (cons 1 '()) \",\")", "expected type `Str` but found `Int`"),
+    ];
+    for (src, msg) in fails {
+        let id = lisp.load(::lisp::compile_str(src).unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            Err(e) => assert_eq!(e.error.to_string(), msg),
+            Ok(v) => panic!("{} returned {:?}", src, v),
+        }
+    }
+}
+
+#[test]
+fn chars() {
+    init_logger();
+    use lisp::read_all;
+
+    let forms = read_all("#\\λ #\\space #\\newline #\\x3bb #\\x #\\s").unwrap();
+    assert_eq!(forms, vec![
+        MemData::Char('λ'), MemData::Char(' '), MemData::Char('\n'),
+        MemData::Char('λ'), MemData::Char('x'), MemData::Char('s'),
+    ]);
+    assert!(read_all("#\\spaces").is_err());
+    assert!(read_all("#\\xd800").is_err());
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let cases = vec![
+        ("(string-ref \"aλb\" 1)", MemData::Char('λ')),
+        ("(char->int #\\λ)", MemData::Int(0x3bb)),
+        ("(int->char 955)", MemData::Char('λ')),
+        ("(list->str (str->list \"añλ\"))", MemData::Str("añλ".to_owned())),
+        ("(< #\\a #\\b)", MemData::Bool(true)),
+        ("(> #\\λ #\\z)", MemData::Bool(true)),
+        ("(char-alphabetic? #\\λ)", MemData::Bool(true)),
+        ("(char-alphabetic? #\\3)", MemData::Bool(false)),
+        ("(char-numeric? #\\3)", MemData::Bool(true)),
+        ("(char-whitespace? #\\tab)", MemData::Bool(true)),
+        ("(char-whitespace? #\\x)", MemData::Bool(false)),
+        ("(char-upcase #\\λ)", MemData::Char('Λ')),
+        ("(char-downcase #\\A)", MemData::Char('a')),
+        // No single-char uppercase form
+        ("(char-upcase #\\ß)", MemData::Char('ß')),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+
+    let id = lisp.load(::lisp::compile_str("(char-upcase \"a\")").unwrap(), reuse).unwrap();
+    match lisp.call(&id) {
+        Err(e) => assert_eq!(e.error.to_string(), "expected type `Char` but found `Str`"),
+        Ok(v) => panic!("returned {:?}", v),
+    }
+}
+
+#[test]
+fn equality() {
+    init_logger();
+    use std::rc::Rc;
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "(define p (cons 1 (cons 2 '())))", reuse);
+    run(&mut lisp, "(define v (vector 1 \"a\" p))", reuse);
+    let cases = vec![
+        // Identity
+        ("(eq? p p)", true),
+        ("(eq? p (cons 1 (cons 2 '())))", false),
+        ("(eq? v v)", true),
+        ("(eq? (vector) (vector))", false),
+        ("(eq? 'a 'a)", true),
+        ("(eq? 1 1.0)", false),
+        ("(eq? 0.0 -0.0)", false),
+        ("(let ((m (make-map 1 2))) (eq? m m))", true),
+        ("(let ((f (lambda (x) x))) (eq? f f))", true),
+        // Value
+        ("(eqv? 0.0 -0.0)", true),
+        ("(eqv? #\\a #\\a)", true),
+        ("(eqv? \"ab\" (concat \"a\" \"b\"))", true),
+        ("(eqv? p (cons 1 (cons 2 '())))", false),
+        // Structure
+        ("(equal? p (cons 1 (cons 2 '())))", true),
+        ("(equal? p (cons 1 (cons 3 '())))", false),
+        ("(equal? v (vector 1 \"a\" (cons 1 (cons 2 '()))))", true),
+        ("(equal? v (vector 1 \"a\"))", false),
+        ("(equal? (make-map 'k (vector 1)) (make-map 'k (vector 1)))", true),
+        ("(equal? 1 1.0)", false),
+        ("(= p (cons 1 (cons 2 '())))", true),
+        ("(= 1 1.0)", true),
+        // Ordering
+        ("(< \"abc\" \"abd\")", true),
+        ("(> \"b\" \"abc\")", true),
+        ("(< #\\a #\\b #\\c)", true),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), MemData::Bool(expected), "{}", src);
+    }
+
+    // Pointer wrapping doesn't change the result
+    let a = MemData::list(vec![MemData::Int(1), MemData::Str("x".to_owned())]);
+    let b = MemData::Pointer(Rc::new(MemData::Pointer(Rc::new(a.clone()))));
+    assert!(a.is_eq(&b) && a.is_eqv(&b) && a.is_equal(&b));
+    assert!(MemData::Pointer(Rc::new(MemData::Float(2.0))).is_eqv(&MemData::Float(2.0)));
+
+    // Cyclic structures compare without looping forever
+    run(&mut lisp, "(define (ring x) (let ((p (cons 1 (cons x '())))) (set-cdr! (cdr p) p) p))", reuse);
+    run(&mut lisp, "(define (selfish x) (let ((v (vector x 0))) (vector-set! v 1 v) v))", reuse);
+    let cycles = vec![
+        ("(equal? (ring 2) (ring 2))", true),
+        ("(equal? (ring 2) (ring 3))", false),
+        ("(= (ring 2) (ring 2))", true),
+        ("(let ((r (ring 2))) (equal? r (cons 1 (cons 2 r))))", true),
+        ("(equal? (selfish 1) (selfish 1))", true),
+        ("(equal? (selfish 1) (selfish 2))", false),
+        ("(let ((m (make-map 'k 1)) (n (make-map 'k 1))) (map-set! m 'k m) (map-set! n 'k n) (equal? m n))", true),
+    ];
+    for (src, expected) in cycles {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), MemData::Bool(expected), "{}", src);
+    }
+    run(&mut lisp, "(define-record-type node (make-node next) node? (next node-next set-node-next!))", reuse);
+    let knot = "(let ((n (make-node '()))) (set-node-next! n n) n)";
+    assert_eq!(run(&mut lisp, knot, reuse), run(&mut lisp, knot, reuse));
+    assert_eq!(run(&mut lisp, "(ring 2)", reuse), run(&mut lisp, "(ring 2)", reuse));
+    assert!(run(&mut lisp, "(ring 2)", reuse) != run(&mut lisp, "(ring 3)", reuse));
+}
+
+#[test]
+fn printer() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "(define (square x) (* x x))", reuse);
+    let cases = vec![
+        ("'(1 2 3)", "(1 2 3)", "(1 2 3)"),
+        ("(cons 1 2)", "(1 . 2)", "(1 . 2)"),
+        ("'(a (\"s\" #\\c) . 2.5)", "(a (s c) . 2.5)", "(a (\"s\" #\\c) . 2.5)"),
+        ("\"tab\\there \\\"q\\\"\"", "tab\there \"q\"", "\"tab\\there \\\"q\\\"\""),
+        ("(vector 1 #\\space '())", "#(1   ())", "#(1 #\\space ())"),
+        ("(make-map 'b 2 'a 1)", "#<map (a . 1) (b . 2)>", "#<map (a . 1) (b . 2)>"),
+        ("123456789012345678901234567890", "123456789012345678901234567890", "123456789012345678901234567890"),
+        ("(> 2 1)", "#t", "#t"),
+        ("square", "#<procedure square>", "#<procedure square>"),
+        ("(lambda (x) x)", "#<procedure>", "#<procedure>"),
+        ("(let ((p (cons 1 (cons 2 '())))) (set-cdr! (cdr p) p) p)", "(1 2 . #<cycle>)", "(1 2 . #<cycle>)"),
+        ("(let ((p (cons 1 2))) (set-car! p p) p)", "(#<cycle> . 2)", "(#<cycle> . 2)"),
+        ("(let ((v (vector 1 2))) (vector-set! v 0 v) v)", "#(#<cycle> 2)", "#(#<cycle> 2)"),
+    ];
+    for (src, display, write) in cases {
+        let v = run(&mut lisp, src, reuse);
+        assert_eq!(v.print(PrintMode::Display), display, "{}", src);
+        assert_eq!(v.print(PrintMode::Write), write, "{}", src);
+    }
+
+    // The same list twice isn't a cycle
+    let shared = MemData::list(vec![MemData::Int(1)]);
+    let both = MemData::list(vec![shared.clone(), shared]);
+    assert_eq!(both.print(PrintMode::Write), "((1) (1))");
+
+    // Long lists print in linear time, with the cycle check on every cdr
+    let s = "ab".repeat(100000);
+    let chars = run(&mut lisp, &format!("(str->list \"{}\")", s), reuse);
+    let printed = chars.print(PrintMode::Display);
+    assert_eq!(printed.len(), 2 + 200000 * 2 - 1);
+
+    match ::lisp::compile_str("(if)") {
+        Err(e) => assert_eq!(e.to_string(),
+            "compile error: if takes a condition, a consequent and an optional alternative: `(if)`"),
+        Ok(_) => panic!("compiled a malformed if"),
+    }
+
+    // Runtime errors name variables rather than showing their ids
+    let mut lisp: vm::VM = vm::VM::new();
+    let id = lisp.load(::lisp::compile_str("(+ no-such-var 1)").unwrap(), vm::LoadOpts::REUSE_VAR_STRINGS).unwrap();
+    match lisp.call(&id) {
+        Err(e) => assert!(e.error.to_string().ends_with("variable not found in scope `0`: `no-such-var`"), "{}", e.error),
+        Ok(v) => panic!("returned {:?}", v),
+    }
+}
+
+#[test]
+fn records() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    let rtd = run(&mut lisp, "
+        (define-record-type <point>
+          (make-point x y)
+          point?
+          (x point-x set-point-x!)
+          (y point-y))", reuse);
+    assert_eq!(rtd.print(PrintMode::Write), "#<record-type point>");
+    run(&mut lisp, "(define-record-type color (make-color name) color? (rgb color-rgb) (name color-name))", reuse);
+    run(&mut lisp, "(define p (make-point 1 2))", reuse);
+
+    let cases = vec![
+        ("(point-x p)", MemData::Int(1)),
+        ("(point-y p)", MemData::Int(2)),
+        ("(point? p)", MemData::Bool(true)),
+        ("(point? (make-color \"red\"))", MemData::Bool(false)),
+        ("(point? '(1 2))", MemData::Bool(false)),
+        // Mutation is shared by every holder of the record
+        ("(let ((q p)) (set-point-x! q 10) (point-x p))", MemData::Int(10)),
+        ("(color-rgb (make-color \"red\"))", MemData::Nil),
+        ("(eq? p p)", MemData::Bool(true)),
+        ("(equal? (make-point 1 2) (make-point 1 2))", MemData::Bool(false)),
+        // Defined in a function body too
+        ("(do (define-record-type box (make-box v) box? (v unbox)) (unbox (make-box 'x)))",
+         MemData::Symbol(Symbol::intern("x"))),
+    ];
+    for (src, expected) in cases {
+        assert_eq!(*run(&mut lisp, src, reuse).deref(), expected, "{}", src);
+    }
+    assert_eq!(run(&mut lisp, "(make-point \"a\" #\\b)", reuse).print(PrintMode::Write),
+               "#<point x: \"a\" y: #\\b>");
+
+    let fails = vec![
+        ("(point-x (make-color \"red\"))", "expected a record of type `point` but found one of type `color`"),
+        ("(set-point-x! (make-color \"red\") 1)", "expected a record of type `point` but found one of type `color`"),
+        ("(point-y 5)", "expected type `Record` but found `Int`"),
+    ];
+    for (src, msg) in fails {
+        let id = lisp.load(::lisp::compile_str(src).unwrap(), reuse).unwrap();
+        match lisp.call(&id) {
+            // Raised inside the accessor, so wrapped as a subjob error
+            Err(e) => assert!(e.error.to_string().ends_with(msg), "{}", e.error),
+            Ok(v) => panic!("{} returned {:?}", src, v),
+        }
+    }
+
+    // A record of another type is a type error like any other
+    let id = lisp.load(::lisp::compile_str("(point-x (make-color \"red\"))").unwrap(), reuse).unwrap();
+    let mut e = lisp.call(&id).unwrap_err().error;
+    while let Error::RuntimeErrorInSubJob(inner) = e {
+        e = inner.error;
+    }
+    match e {
+        Error::TypeError(Type::Record, Type::Record, Some((expected, found))) =>
+            assert_eq!((expected.as_str(), found.as_str()), ("point", "color")),
+        e => panic!("unexpected error: {:?}", e),
+    }
+
+    // A constructor op that doesn't match the fields
+    let bin = vm::assemble("LVR #Symbol(\"p\")\nLVR #Symbol(\"x\")\nRTD 1\nRNW 0").unwrap();
+    let id = lisp.load(bin, reuse).unwrap();
+    match lisp.call(&id) {
+        Err(e) => assert_eq!(e.error.to_string(), "expected 1 arguments but found 0"),
+        Ok(v) => panic!("returned {:?}", v),
+    }
+
+    for src in &["(define-record-type t (make-t z) t? (x t-x))", "(define-record-type t (make-t) t? (x))"] {
+        match ::lisp::compile_str(src) {
+            Err(Error::CompileError(..)) => (),
+            r => panic!("{} compiled to {:?}", src, r.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn gc() {
+    init_logger();
+
+    let mut lisp: vm::VM = vm::VM::new();
+    let reuse = vm::LoadOpts::REUSE_VAR_STRINGS;
+    run(&mut lisp, "
+        (define (count n)
+          (define (loop i acc) (if (> i n) acc (loop (+ i 1) (+ acc 1))))
+          (loop 1 0))", reuse);
+    run(&mut lisp, "(define cyclic (lambda () (let ((p (cons 1 (cons 2 '())))) (set-cdr! (cdr p) p) 'done)))", reuse);
+    for _ in 0..10 {
+        run(&mut lisp, "(count 3)", reuse);
+        run(&mut lisp, "(cyclic)", reuse);
+    }
+
+    let stats = lisp.gc();
+    assert_eq!(stats.collections, 1);
+    assert!(stats.last_reclaimed >= 20, "{:?}", stats);
+    assert_eq!(lisp.gc().last_reclaimed, 0);
+
+    // Cycles still held from the outside or from globals survive
+    let held = run(&mut lisp, "(let ((v (vector 1 2))) (vector-set! v 1 v) v)", reuse);
+    run(&mut lisp, "(define g (cons 'a (cons 'b '())))", reuse);
+    run(&mut lisp, "(set-cdr! (cdr g) g)", reuse);
+    lisp.gc();
+    assert_eq!(held.print(PrintMode::Write), "#(1 #<cycle>)");
+    assert_eq!(*run(&mut lisp, "(car (cdr (cdr g)))", reuse).deref(), MemData::Symbol(Symbol::intern("a")));
+    assert_eq!(*run(&mut lisp, "(count 5)", reuse).deref(), MemData::Int(5));
+    assert_eq!(lisp.heap_stats().collections, 2 + 1);
+}
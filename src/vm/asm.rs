@@ -0,0 +1,451 @@
+//! Textual form of bytecode, as written throughout spec.md
+//!
+//!     ; comments run to the end of the line
+//!     PSS
+//!         DVR 'a' #Int(10) &
+//!         LVR 'a'
+//!         CNV (1) <Str>
+//!         DSP
+//!     PPS
+//!
+//! Operands of an op, in any order:
+//!
+//!     'name' | name   identifier, bound to the var string `name`
+//!     @3              identifier without a var string
+//!     (3) | #3 | 3    quantifier
+//!     #Int(10)        inline constant: Int, BigInt, Float, Str, Char, Bool, Symbol, Nil,
+//!                     Pair(car, cdr), Inst(op) or Proc(op, op, ...)
+//!     <Str> | <str>   type
+//!     &               mute
+
+use super::{
+    Bin,
+    Op,
+    OpCode,
+    MemData,
+    Symbol,
+    IdentID,
+    ConstID,
+    Quantif,
+    MapKey,
+    Error,
+    OPCODES,
+    TYPES,
+};
+
+use std::collections::HashMap;
+use std::iter::Peekable;
+use std::str::Chars;
+
+struct Assembler<'a> {
+    chars:  Peekable<Chars<'a>>,
+    line:   usize,
+    column: usize,
+
+    idents: Vec<IdentID>,
+    var_strings: HashMap<IdentID, String>,
+    named: HashMap<String, IdentID>,
+    anonymous: HashMap<u32, IdentID>,
+    consts: Vec<MemData>,
+    /// Where the hashable constants went, so repeating one reuses it
+    const_index: HashMap<MapKey, ConstID>,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            chars: src.chars().peekable(),
+            line: 1,
+            column: 1,
+
+            idents: Vec::new(),
+            var_strings: HashMap::new(),
+            named: HashMap::new(),
+            anonymous: HashMap::new(),
+            consts: Vec::new(),
+            const_index: HashMap::new(),
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.chars.peek().copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next();
+        if let Some(c) = c {
+            if c == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
+        c
+    }
+
+    #[inline]
+    fn error(&self, msg: &'static str) -> Error {
+        Error::SyntaxError(self.line, self.column, msg)
+    }
+
+    /// Skips blanks and comments, stopping at newlines unless `newlines` is set
+    fn skip_blanks(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            if c == ';' {
+                while self.peek().is_some_and(|c| c != '\n') {
+                    self.bump();
+                }
+            } else if c.is_whitespace() && (newlines || c != '\n') {
+                self.bump();
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn expect(&mut self, c: char, msg: &'static str) -> Result<(), Error> {
+        self.skip_blanks(true);
+        if self.peek() == Some(c) {
+            self.bump();
+            Ok(())
+        } else {
+            Err(self.error(msg))
+        }
+    }
+
+    fn word(&mut self) -> String {
+        let mut w = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_alphanumeric() || "_-+*/=!?%.".contains(c) {
+                w.push(c);
+                self.bump();
+            } else {
+                break;
+            }
+        }
+        w
+    }
+
+    fn number(&mut self) -> Result<u32, Error> {
+        self.number_from(String::new())
+    }
+
+    /// Reads digits following the already consumed `w`
+    fn number_from<T: ::std::str::FromStr>(&mut self, mut w: String) -> Result<T, Error> {
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() { break; }
+            w.push(c);
+            self.bump();
+        }
+        w.parse().map_err(|_| self.error("expected a number"))
+    }
+
+    fn named_ident(&mut self, name: String) -> IdentID {
+        if let Some(id) = self.named.get(&name) {
+            return *id;
+        }
+        let id = self.idents.len() as IdentID;
+        self.idents.push(id);
+        self.var_strings.insert(id, name.clone());
+        self.named.insert(name, id);
+        id
+    }
+
+    fn anonymous_ident(&mut self, n: u32) -> IdentID {
+        if let Some(id) = self.anonymous.get(&n) {
+            return *id;
+        }
+        let id = self.idents.len() as IdentID;
+        self.idents.push(id);
+        self.anonymous.insert(n, id);
+        id
+    }
+
+    fn konst(&mut self, val: MemData) -> ConstID {
+        let id = self.consts.len() as ConstID;
+        if let Ok(k) = MapKey::new(&val) {
+            if let Some(&i) = self.const_index.get(&k) {
+                return i;
+            }
+            self.const_index.insert(k, id);
+        }
+        self.consts.push(val);
+        id
+    }
+
+    /// Reads the body of a `"..."` or `'...'` literal using Rust's escapes
+    fn quoted(&mut self, quote: char) -> Result<String, Error> {
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("unterminated literal")),
+                Some(c) if c == quote => return Ok(s),
+                Some('\\') => s.push(match self.bump() {
+                    Some('n')  => '\n',
+                    Some('t')  => '\t',
+                    Some('r')  => '\r',
+                    Some('0')  => '\0',
+                    Some('\\') => '\\',
+                    Some('\'') => '\'',
+                    Some('"')  => '"',
+                    Some('u') => {
+                        self.expect('{', "expected `{` in a unicode escape")?;
+                        let mut hex = String::new();
+                        while let Some(c) = self.bump() {
+                            if c == '}' { break; }
+                            hex.push(c);
+                        }
+                        u32::from_str_radix(&hex, 16).ok()
+                            .and_then(::std::char::from_u32)
+                            .ok_or_else(|| self.error("bad unicode escape"))?
+                    },
+                    _ => return Err(self.error("unknown escape sequence")),
+                }),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn constant(&mut self) -> Result<MemData, Error> {
+        self.skip_blanks(true);
+        let kind = self.word();
+        if kind == "Nil" {
+            return Ok(MemData::Nil);
+        }
+        self.expect('(', "expected `(` after the constant type")?;
+        self.skip_blanks(true);
+
+        let v = match kind.as_str() {
+            "Int" => {
+                let mut w = String::new();
+                if self.peek() == Some('-') {
+                    self.bump();
+                    w.push('-');
+                }
+                MemData::Int(self.number_from(w)?)
+            },
+            "BigInt" => {
+                let mut w = String::new();
+                if self.peek() == Some('-') {
+                    self.bump();
+                    w.push('-');
+                }
+                MemData::integer(self.number_from(w)?)
+            },
+            "Float" => self.word().parse().map(MemData::Float)
+                .map_err(|_| self.error("expected a float"))?,
+            "Bool" => match self.word().as_str() {
+                "true"  => MemData::Bool(true),
+                "false" => MemData::Bool(false),
+                _ => return Err(self.error("expected `true` or `false`")),
+            },
+            "Str" | "Symbol" => {
+                self.expect('"', "expected a string literal")?;
+                let s = self.quoted('"')?;
+                if kind == "Str" { MemData::Str(s) } else { MemData::Symbol(Symbol::intern(&s)) }
+            },
+            "Char" => {
+                self.expect('\'', "expected a character literal")?;
+                let s = self.quoted('\'')?;
+                let mut cs = s.chars();
+                match (cs.next(), cs.next()) {
+                    (Some(c), None) => MemData::Char(c),
+                    _ => return Err(self.error("expected a single character")),
+                }
+            },
+            "Pair" => {
+                let car = self.constant()?;
+                self.expect(',', "expected `,` between car and cdr")?;
+                MemData::cons(car, self.constant()?)
+            },
+            "Inst" => MemData::Inst(self.op()?),
+            "Proc" => {
+                let mut ops = vec![self.op()?];
+                self.skip_blanks(true);
+                while self.peek() == Some(',') {
+                    self.bump();
+                    ops.push(self.op()?);
+                    self.skip_blanks(true);
+                }
+                MemData::Proc(ops.into())
+            },
+            _ => return Err(self.error("unknown constant type")),
+        };
+        self.expect(')', "expected `)` to close the constant")?;
+        Ok(v)
+    }
+
+    fn op(&mut self) -> Result<Op, Error> {
+        self.skip_blanks(true);
+        let mnemonic = self.word();
+        let opcode = OPCODES.iter()
+            .find(|o| format!("{:?}", o) == mnemonic)
+            .cloned()
+            .ok_or_else(|| self.error("unknown opcode"))?;
+
+        let mut op = Op::new(opcode, None, None, None, None, false);
+        loop {
+            self.skip_blanks(false);
+            let c = match self.peek() {
+                None | Some('\n') | Some(',') | Some(')') => break,
+                Some(c) => c,
+            };
+
+            match c {
+                '\'' => {
+                    self.bump();
+                    let name = self.quoted('\'')?;
+                    op.ident = Some(self.named_ident(name));
+                },
+                '@' => {
+                    self.bump();
+                    let n = self.number()?;
+                    op.ident = Some(self.anonymous_ident(n));
+                },
+                '(' => {
+                    self.bump();
+                    op.n = Some(self.number()? as Quantif);
+                    self.expect(')', "expected `)` after the quantifier")?;
+                },
+                '#' => {
+                    self.bump();
+                    // spec.md also writes quantifiers as `#1`
+                    if self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        op.n = Some(self.number()? as Quantif);
+                    } else {
+                        let v = self.constant()?;
+                        op.val = Some(self.konst(v));
+                    }
+                },
+                '<' => {
+                    self.bump();
+                    let name = self.word();
+                    op.typ = Some(TYPES.iter()
+                                  .find(|t| format!("{:?}", t).eq_ignore_ascii_case(&name))
+                                  .cloned()
+                                  .ok_or_else(|| self.error("unknown type"))?);
+                    self.expect('>', "expected `>` after the type")?;
+                },
+                '&' => {
+                    self.bump();
+                    op.mute = true;
+                },
+                c if c.is_ascii_digit() => op.n = Some(self.number()? as Quantif),
+                c if c.is_alphabetic() || c == '_' => {
+                    let name = self.word();
+                    op.ident = Some(self.named_ident(name));
+                },
+                _ => return Err(self.error("unexpected character in operands")),
+            }
+        }
+        Ok(op)
+    }
+
+    fn program(mut self) -> Result<Bin, Error> {
+        let mut insts = Vec::new();
+        loop {
+            self.skip_blanks(true);
+            if self.peek().is_none() { break; }
+
+            insts.push(self.op()?);
+            self.skip_blanks(false);
+            match self.bump() {
+                None | Some('\n') => (),
+                Some(_) => return Err(self.error("expected the end of the line")),
+            }
+        }
+        Ok(Bin::new(insts.into(), self.idents, self.var_strings, self.consts))
+    }
+}
+
+/// Assembles `.ulasm` text into a `Bin`
+pub fn assemble(src: &str) -> Result<Bin, Error> {
+    Assembler::new(src).program()
+}
+
+fn fmt_op(op: &Op, idents: &HashMap<IdentID, String>, consts: &[MemData]) -> String {
+    let mut s = format!("{:?}", op.opcode);
+    if let Some(i) = op.ident {
+        s += &match idents.get(&i) {
+            Some(name) => format!(" '{}'", name.replace('\\', "\\\\").replace('\'', "\\'")),
+            None => format!(" @{}", i),
+        };
+    }
+    if let Some(n) = op.n {
+        s += &format!(" ({})", n);
+    }
+    if let Some(c) = op.val {
+        s += &match consts.get(c as usize) {
+            Some(v) => format!(" #{}", fmt_const(v, idents, consts)),
+            None => format!(" #Missing({})", c),
+        };
+    }
+    if let Some(t) = op.typ {
+        s += &format!(" <{:?}>", t);
+    }
+    if op.mute {
+        s += " &";
+    }
+    s
+}
+
+fn fmt_const(v: &MemData, idents: &HashMap<IdentID, String>, consts: &[MemData]) -> String {
+    match *v.deref() {
+        MemData::Int(i)          => format!("Int({})", i),
+        MemData::Float(f)        => format!("Float({:?})", f),
+        MemData::BigInt(ref b)   => format!("BigInt({})", b),
+        MemData::Str(ref s)      => format!("Str({:?})", s),
+        MemData::Symbol(s)       => format!("Symbol({:?})", s),
+        MemData::Char(c)         => format!("Char({:?})", c),
+        MemData::Bool(b)         => format!("Bool({})", b),
+        MemData::Nil             => "Nil".to_owned(),
+        MemData::Pair(ref c) => {
+            // Lists are walked in a loop so long ones don't nest calls
+            let (mut s, mut pairs) = (String::new(), 0);
+            let mut end = MemData::Pair(c.clone());
+            while let MemData::Pair(c) = end.deref().clone() {
+                s += &format!("Pair({}, ", fmt_const(&c.car(), idents, consts));
+                pairs += 1;
+                end = c.cdr();
+            }
+            s += &fmt_const(&end, idents, consts);
+            s + &")".repeat(pairs)
+        },
+        MemData::Inst(ref op)    => format!("Inst({})", fmt_op(op, idents, consts)),
+        MemData::Proc(ref p)     => format!("Proc({})", p.iter()
+                                             .map(|op| fmt_op(op, idents, consts))
+                                             .collect::<Vec<String>>()
+                                             .join(", ")),
+        ref v                    => format!("{:?}(..)", v.get_type()),
+    }
+}
+
+/// Renders a `Bin` as `.ulasm` text, indenting the bodies recorded by `REC`
+pub fn disassemble(bin: &Bin) -> String {
+    let mut out = String::new();
+    // Instructions left to record at each level of `REC` nesting
+    let mut recording: Vec<usize> = Vec::new();
+
+    for op in bin.insts().iter() {
+        for _ in 0..recording.len() {
+            out.push_str("    ");
+        }
+        out.push_str(&fmt_op(op, bin.var_strings(), bin.consts()));
+        out.push('\n');
+
+        for left in recording.iter_mut() {
+            *left -= 1;
+        }
+        while recording.last() == Some(&0) {
+            recording.pop();
+        }
+        if op.opcode == OpCode::REC {
+            let n = op.n.unwrap_or(1) as usize;
+            if n > 0 {
+                recording.push(n);
+            }
+        }
+    }
+    out
+}
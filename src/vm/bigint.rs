@@ -0,0 +1,362 @@
+//! Arbitrary precision integers, for the results that don't fit an `i64`
+//!
+//! Integer arithmetic promotes to `BigInt` on overflow and demotes back to
+//! `Int` whenever a result fits again (see `MemData::integer`), so a `BigInt`
+//! value is always outside of the `i64` range.
+
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+use std::ops::{
+    Add,
+    Sub,
+    Mul,
+    Div,
+};
+
+const BASE: u64 = 1 << 32;
+/// Largest power of ten fitting a limb, used to print and parse
+const DECIMAL_BASE: u32 = 1_000_000_000;
+const DECIMAL_DIGITS: usize = 9;
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub struct BigInt {
+    negative: bool,
+    /// Magnitude in base 2^32, least significant limb first, without
+    /// trailing zero limbs (zero is the empty vec and never negative)
+    limbs: Vec<u32>,
+}
+
+impl BigInt {
+    pub fn from_parts(negative: bool, limbs: Vec<u32>) -> Self {
+        Self { negative, limbs }.normalized()
+    }
+
+    pub fn from_i64(v: i64) -> Self {
+        let m = v.unsigned_abs();
+        Self::from_parts(v < 0, vec![m as u32, (m >> 32) as u32])
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
+    pub fn limbs(&self) -> &[u32] {
+        &self.limbs
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    pub fn to_i64(&self) -> Option<i64> {
+        if self.limbs.len() > 2 {
+            return None;
+        }
+        let m = self.limbs.iter().rev().fold(0u64, |a, l| a << 32 | *l as u64);
+        if self.negative {
+            if m <= 1 << 63 { Some((m as i64).wrapping_neg()) } else { None }
+        } else if m <= i64::MAX as u64 {
+            Some(m as i64)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        let m = self.limbs.iter().rev().fold(0.0, |a, l| a * BASE as f64 + *l as f64);
+        if self.negative { -m } else { m }
+    }
+
+    fn normalized(mut self) -> Self {
+        while self.limbs.last() == Some(&0) {
+            self.limbs.pop();
+        }
+        if self.limbs.is_empty() {
+            self.negative = false;
+        }
+        self
+    }
+
+    /// Truncating division and its remainder, `None` when dividing by zero
+    pub fn div_rem(&self, other: &BigInt) -> Option<(BigInt, BigInt)> {
+        if other.is_zero() {
+            return None;
+        }
+        let (q, r) = div_rem_mag(&self.limbs, &other.limbs);
+        Some((BigInt::from_parts(self.negative != other.negative, q),
+              BigInt::from_parts(self.negative, r)))
+    }
+}
+
+fn cmp_mag(a: &[u32], b: &[u32]) -> Ordering {
+    a.len().cmp(&b.len()).then_with(|| a.iter().rev().cmp(b.iter().rev()))
+}
+
+fn add_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let (a, b) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut r = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for (i, l) in a.iter().enumerate() {
+        let s = *l as u64 + *b.get(i).unwrap_or(&0) as u64 + carry;
+        r.push(s as u32);
+        carry = s >> 32;
+    }
+    if carry > 0 {
+        r.push(carry as u32);
+    }
+    r
+}
+
+/// `a - b` for `a >= b`
+fn sub_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len());
+    let mut borrow = 0i64;
+    for (i, l) in a.iter().enumerate() {
+        let mut d = *l as i64 - *b.get(i).unwrap_or(&0) as i64 - borrow;
+        borrow = if d < 0 { d += BASE as i64; 1 } else { 0 };
+        r.push(d as u32);
+    }
+    r
+}
+
+fn mul_mag(a: &[u32], b: &[u32]) -> Vec<u32> {
+    let mut r = vec![0u32; a.len() + b.len()];
+    for (i, x) in a.iter().enumerate() {
+        let mut carry = 0u64;
+        for (j, y) in b.iter().enumerate() {
+            let t = *x as u64 * *y as u64 + r[i + j] as u64 + carry;
+            r[i + j] = t as u32;
+            carry = t >> 32;
+        }
+        r[i + b.len()] = carry as u32;
+    }
+    r
+}
+
+/// `limbs = limbs * m + a`
+fn mul_small_add(limbs: &mut Vec<u32>, m: u32, a: u32) {
+    let mut carry = a as u64;
+    for l in limbs.iter_mut() {
+        let t = *l as u64 * m as u64 + carry;
+        *l = t as u32;
+        carry = t >> 32;
+    }
+    if carry > 0 {
+        limbs.push(carry as u32);
+    }
+}
+
+fn div_rem_small(a: &[u32], d: u32) -> (Vec<u32>, u32) {
+    let mut q = vec![0u32; a.len()];
+    let mut r = 0u64;
+    for (i, l) in a.iter().enumerate().rev() {
+        let cur = r << 32 | *l as u64;
+        q[i] = (cur / d as u64) as u32;
+        r = cur % d as u64;
+    }
+    (q, r as u32)
+}
+
+/// `a << shift` for `shift < 32`, one limb longer than `a`
+fn shl_mag(a: &[u32], shift: u32) -> Vec<u32> {
+    let mut r = Vec::with_capacity(a.len() + 1);
+    let mut carry = 0u64;
+    for l in a {
+        let t = (*l as u64) << shift | carry;
+        r.push(t as u32);
+        carry = t >> 32;
+    }
+    r.push(carry as u32);
+    r
+}
+
+/// Schoolbook long division of magnitudes a limb at a time (Knuth's algorithm
+/// D), `b` being non-zero
+fn div_rem_mag(a: &[u32], b: &[u32]) -> (Vec<u32>, Vec<u32>) {
+    if b.len() == 1 {
+        let (q, r) = div_rem_small(a, b[0]);
+        return (q, vec![r]);
+    }
+    if cmp_mag(a, b) == Ordering::Less {
+        return (Vec::new(), a.to_vec());
+    }
+
+    // Shifted so the top limb of the divisor has its high bit set, which makes
+    // the quotient limbs guessed from the top two limbs at most 2 too large
+    let shift = b[b.len() - 1].leading_zeros();
+    let mut b = shl_mag(b, shift);
+    b.pop();
+    let mut r = shl_mag(a, shift);
+    let n = b.len();
+    let (top, next) = (b[n - 1] as u64, b[n - 2] as u64);
+
+    let mut q = vec![0u32; a.len() - n + 1];
+    for j in (0..q.len()).rev() {
+        let num = (r[j + n] as u64) << 32 | r[j + n - 1] as u64;
+        let (mut qhat, mut rhat) = (num / top, num % top);
+        while qhat >= BASE || qhat * next > (rhat << 32 | r[j + n - 2] as u64) {
+            qhat -= 1;
+            rhat += top;
+            if rhat >= BASE {
+                break;
+            }
+        }
+
+        // r[j..=j + n] -= qhat * b
+        let (mut borrow, mut carry) = (0i64, 0u64);
+        for i in 0..n {
+            let p = qhat * b[i] as u64 + carry;
+            carry = p >> 32;
+            let t = r[i + j] as i64 - borrow - (p as u32) as i64;
+            r[i + j] = t as u32;
+            borrow = (t < 0) as i64;
+        }
+        let t = r[j + n] as i64 - borrow - carry as i64;
+        r[j + n] = t as u32;
+
+        // Still one too large: add b back
+        if t < 0 {
+            qhat -= 1;
+            let mut carry = 0u64;
+            for i in 0..n {
+                let s = r[i + j] as u64 + b[i] as u64 + carry;
+                r[i + j] = s as u32;
+                carry = s >> 32;
+            }
+            r[j + n] = r[j + n].wrapping_add(carry as u32);
+        }
+        q[j] = qhat as u32;
+    }
+
+    // The remainder is what is left of r, shifted back
+    let mut rem = vec![0u32; n];
+    let mut high = 0u64;
+    for i in (0..n).rev() {
+        rem[i] = ((high << 32 | r[i] as u64) >> shift) as u32;
+        high = r[i] as u64 & ((1 << shift) - 1);
+    }
+    while rem.last() == Some(&0) { rem.pop(); }
+    (q, rem)
+}
+
+impl FromStr for BigInt {
+    type Err = ();
+
+    /// Parses `[sign] digits` in base 10
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (negative, digits) = match s.as_bytes().first() {
+            Some(b'-') => (true, &s[1..]),
+            Some(b'+') => (false, &s[1..]),
+            _ => (false, s),
+        };
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(());
+        }
+
+        let mut limbs = Vec::new();
+        // The first chunk takes the leftover digits so the others are full
+        let mut at = digits.len() % DECIMAL_DIGITS;
+        if at == 0 { at = DECIMAL_DIGITS; }
+        let mut start = 0;
+        while start < digits.len() {
+            let chunk: u32 = digits[start..at].parse().map_err(|_| ())?;
+            let scale = 10u32.pow((at - start) as u32);
+            mul_small_add(&mut limbs, scale, chunk);
+            start = at;
+            at += DECIMAL_DIGITS;
+        }
+        Ok(Self::from_parts(negative, limbs))
+    }
+}
+
+impl Add for &BigInt {
+    type Output = BigInt;
+
+    fn add(self, other: &BigInt) -> BigInt {
+        if self.negative == other.negative {
+            return BigInt::from_parts(self.negative, add_mag(&self.limbs, &other.limbs));
+        }
+        // Opposite signs: the larger magnitude decides the sign
+        match cmp_mag(&self.limbs, &other.limbs) {
+            Ordering::Less => BigInt::from_parts(other.negative, sub_mag(&other.limbs, &self.limbs)),
+            _ => BigInt::from_parts(self.negative, sub_mag(&self.limbs, &other.limbs)),
+        }
+    }
+}
+
+impl Sub for &BigInt {
+    type Output = BigInt;
+
+    fn sub(self, other: &BigInt) -> BigInt {
+        let neg = BigInt { negative: !other.negative, limbs: other.limbs.clone() }.normalized();
+        self + &neg
+    }
+}
+
+impl Mul for &BigInt {
+    type Output = BigInt;
+
+    fn mul(self, other: &BigInt) -> BigInt {
+        BigInt::from_parts(self.negative != other.negative, mul_mag(&self.limbs, &other.limbs))
+    }
+}
+
+impl Div for &BigInt {
+    type Output = BigInt;
+
+    /// Truncates towards zero like `i64` division; panics on a zero divisor
+    fn div(self, other: &BigInt) -> BigInt {
+        self.div_rem(other).expect("division by zero").0
+    }
+}
+
+impl Ord for BigInt {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => cmp_mag(&self.limbs, &other.limbs),
+            (true, true) => cmp_mag(&other.limbs, &self.limbs),
+        }
+    }
+}
+
+impl PartialOrd for BigInt {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+
+        let mut chunks = Vec::new();
+        let mut m = self.limbs.clone();
+        while !m.is_empty() {
+            let (q, r) = div_rem_small(&m, DECIMAL_BASE);
+            chunks.push(r);
+            m = q;
+            while m.last() == Some(&0) { m.pop(); }
+        }
+
+        if self.negative {
+            write!(f, "-")?;
+        }
+        let mut chunks = chunks.iter().rev();
+        write!(f, "{}", chunks.next().unwrap())?;
+        for c in chunks {
+            write!(f, "{:09}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BigInt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
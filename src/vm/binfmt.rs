@@ -0,0 +1,316 @@
+//! On-disk layout of a `Bin` (see "Bin Layout" in spec.md)
+//!
+//!     header:     magic, version, sizeof(MemData), section sizes, terminator
+//!     operations: [u32] (packed `Op`s, see encode.rs)
+//!     idents:     [(IdentID, var string)]
+//!     data:       [MemData]
+//!
+//! Every integer is little-endian.
+
+use super::{
+    Bin,
+    Op,
+    Procedure,
+    Type,
+    MemData,
+    BigInt,
+    Symbol,
+    IdentID,
+    Error,
+};
+
+use std::collections::HashMap;
+use std::io::{
+    Read,
+    Write,
+};
+use std::mem;
+
+pub const BIN_MAGIC: [u8; 4] = *b"ULC\0";
+pub const BIN_VERSION: u16 = 5;
+const BIN_TERMINATOR: [u8; 4] = [0x0a, 0x1a, 0x0a, 0x00];
+
+const HEADER_LEN: usize = 4 + 2 + 2 + 4 + 4 + 4 + 4;
+const NO_VAR_STRING: u32 = u32::MAX;
+/// How deeply constants read may nest pairs in their cars. Lists are read along
+/// their cdrs in a loop, so they may be as long as they like
+const MAX_NESTING: usize = 256;
+
+/// Bounds checked reads over one section of the file
+struct Section<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Section<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < n {
+            return Err(Error::BadBin("section ends in the middle of an item"));
+        }
+        let (r, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(r)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.take(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let b = self.take(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn i64(&mut self) -> Result<i64, Error> {
+        let b = self.take(8)?;
+        let mut le = [0u8; 8];
+        le.copy_from_slice(b);
+        Ok(i64::from_le_bytes(le))
+    }
+
+    fn words(&mut self, len: u32) -> Result<Vec<u32>, Error> {
+        (0..len).map(|_| self.u32()).collect()
+    }
+
+    fn string(&mut self, len: u32) -> Result<String, Error> {
+        let b = self.take(len as usize)?;
+        String::from_utf8(b.to_vec()).map_err(|_| Error::BadBin("string is not valid utf-8"))
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.bytes.first().cloned()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, v: u16) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_i64(out: &mut Vec<u8>, v: i64) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    put_u32(out, s.len() as u32);
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_words(out: &mut Vec<u8>, words: &[u32]) {
+    for w in words {
+        put_u32(out, *w);
+    }
+}
+
+fn write_data(out: &mut Vec<u8>, v: &MemData) -> Result<(), Error> {
+    let v = v.deref();
+    out.push(v.get_type() as u8);
+    match *v {
+        MemData::Str(ref s) => put_str(out, s),
+        MemData::Symbol(s) => put_str(out, s.as_str()),
+        MemData::Pair(ref c) => {
+            // Each pair of a list is followed by its car and then the next pair
+            write_data(out, &c.car())?;
+            let mut end = c.cdr();
+            loop {
+                let next = match *end.deref() {
+                    MemData::Pair(ref c) => c.clone(),
+                    _ => break,
+                };
+                out.push(Type::Pair as u8);
+                write_data(out, &next.car())?;
+                end = next.cdr();
+            }
+            write_data(out, &end)?;
+        },
+        MemData::Int(i)  => put_i64(out, i),
+        MemData::Float(f) => put_i64(out, f.to_bits() as i64),
+        MemData::BigInt(ref b) => {
+            out.push(b.is_negative() as u8);
+            put_u32(out, b.limbs().len() as u32);
+            put_words(out, b.limbs());
+        },
+        MemData::Char(c) => put_u32(out, c as u32),
+        MemData::Bool(b) => out.push(b as u8),
+        MemData::Nil => (),
+        MemData::Inst(ref op) => {
+            let mut words = Vec::new();
+            op.encode(&mut words)?;
+            put_u32(out, words.len() as u32);
+            put_words(out, &words);
+        },
+        MemData::Proc(ref p) => {
+            let words = p.encode()?;
+            put_u32(out, words.len() as u32);
+            put_words(out, &words);
+        },
+        ref v => return Err(Error::Unserializable(v.get_type())),
+    }
+    Ok(())
+}
+
+fn read_data(s: &mut Section, depth: usize) -> Result<MemData, Error> {
+    if depth > MAX_NESTING {
+        return Err(Error::BadBin("constant is nested too deeply"));
+    }
+    let typ = Type::from_u8(s.u8()?).ok_or(Error::BadBin("unknown type"))?;
+    Ok(match typ {
+        Type::Str => {
+            let len = s.u32()?;
+            MemData::Str(s.string(len)?)
+        },
+        Type::Symbol => {
+            let len = s.u32()?;
+            MemData::Symbol(Symbol::intern(&s.string(len)?))
+        },
+        Type::Pair => {
+            // Each pair of a list is followed by its car and then the next pair
+            let mut cars = vec![read_data(s, depth + 1)?];
+            while s.peek() == Some(Type::Pair as u8) {
+                s.u8()?;
+                cars.push(read_data(s, depth + 1)?);
+            }
+            let end = read_data(s, depth)?;
+            cars.into_iter().rev().fold(end, |l, v| MemData::cons(v, l))
+        },
+        Type::Int  => MemData::Int(s.i64()?),
+        Type::Float => MemData::Float(f64::from_bits(s.i64()? as u64)),
+        Type::BigInt => {
+            let negative = s.u8()? != 0;
+            let len = s.u32()?;
+            MemData::integer(BigInt::from_parts(negative, s.words(len)?))
+        },
+        Type::Char => MemData::Char(char::from_u32(s.u32()?).ok_or(Error::BadBin("invalid char"))?),
+        Type::Bool => MemData::Bool(s.u8()? != 0),
+        Type::Nil  => MemData::Nil,
+        Type::Inst => {
+            let len = s.u32()?;
+            let words = s.words(len)?;
+            let (op, used) = Op::decode(&words)?;
+            if used != words.len() {
+                return Err(Error::BadBin("trailing words after an instruction"));
+            }
+            MemData::Inst(op)
+        },
+        Type::Proc => {
+            let len = s.u32()?;
+            MemData::Proc(Procedure::decode(&s.words(len)?)?)
+        },
+        _ => return Err(Error::BadBin("constant of an unserializable type")),
+    })
+}
+
+/// Reads the next `len` bytes, which the header promised; a lying header fails
+/// once the input runs out instead of allocating whatever it asked for
+fn read_section<R: Read>(r: &mut R, len: usize) -> Result<Vec<u8>, Error> {
+    let mut section = Vec::new();
+    r.take(len as u64).read_to_end(&mut section)?;
+    if section.len() != len {
+        return Err(Error::BadBin("file is shorter than its header says"));
+    }
+    Ok(section)
+}
+
+impl Bin {
+    /// Serializes the bin to the layout described in spec.md
+    pub fn write_to<W: Write>(&self, w: &mut W) -> Result<(), Error> {
+        let mut ops = Vec::new();
+        put_words(&mut ops, &self.insts().encode()?);
+
+        let mut idents = Vec::new();
+        for id in self.idents() {
+            put_u16(&mut idents, *id);
+            match self.var_strings().get(id) {
+                Some(s) => put_str(&mut idents, s),
+                None => put_u32(&mut idents, NO_VAR_STRING),
+            }
+        }
+
+        let mut data = Vec::new();
+        for v in self.consts() {
+            write_data(&mut data, v)?;
+        }
+
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(&BIN_MAGIC);
+        put_u16(&mut header, BIN_VERSION);
+        put_u16(&mut header, mem::size_of::<MemData>() as u16);
+        put_u32(&mut header, ops.len() as u32);
+        put_u32(&mut header, idents.len() as u32);
+        put_u32(&mut header, data.len() as u32);
+        header.extend_from_slice(&BIN_TERMINATOR);
+
+        w.write_all(&header)?;
+        w.write_all(&ops)?;
+        w.write_all(&idents)?;
+        w.write_all(&data)?;
+        Ok(())
+    }
+
+    /// Reads a bin written by `write_to`, rejecting files from other versions
+    pub fn read_from<R: Read>(r: &mut R) -> Result<Bin, Error> {
+        let mut header = [0u8; HEADER_LEN];
+        r.read_exact(&mut header[..4])?;
+        if header[..4] != BIN_MAGIC {
+            return Err(Error::BadBin("not a uLisp bytecode file"));
+        }
+        r.read_exact(&mut header[4..])?;
+
+        let mut h = Section { bytes: &header[4..] };
+        let version = h.u16()?;
+        if version != BIN_VERSION {
+            return Err(Error::UnsupportedBinVersion(version));
+        }
+        if h.u16()? as usize != mem::size_of::<MemData>() {
+            return Err(Error::BadBin("compiled for a different MemData layout"));
+        }
+        let ops_len = h.u32()? as usize;
+        let idents_len = h.u32()? as usize;
+        let data_len = h.u32()? as usize;
+        if h.take(4)? != BIN_TERMINATOR {
+            return Err(Error::BadBin("bad header terminator"));
+        }
+
+        let ops = read_section(r, ops_len)?;
+        let idents = read_section(r, idents_len)?;
+        let data = read_section(r, data_len)?;
+        let (ops, idents, data) = (&ops[..], &idents[..], &data[..]);
+
+        if !ops_len.is_multiple_of(4) {
+            return Err(Error::BadBin("operations section is not made of whole words"));
+        }
+        let mut s = Section { bytes: ops };
+        let insts = Procedure::decode(&s.words((ops_len / 4) as u32)?)?;
+
+        let mut s = Section { bytes: idents };
+        let mut ids: Vec<IdentID> = Vec::new();
+        let mut var_strings = HashMap::new();
+        while !s.is_empty() {
+            let id = s.u16()?;
+            let len = s.u32()?;
+            if len != NO_VAR_STRING {
+                var_strings.insert(id, s.string(len)?);
+            }
+            ids.push(id);
+        }
+
+        let mut s = Section { bytes: data };
+        let mut consts = Vec::new();
+        while !s.is_empty() {
+            consts.push(read_data(&mut s, 0)?);
+        }
+
+        Ok(Bin::new(insts, ids, var_strings, consts))
+    }
+}
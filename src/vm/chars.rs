@@ -0,0 +1,56 @@
+//! Char operations run by `CHR n`, `n` being the index of the operation in
+//! `CHAR_OPS`
+
+use super::{
+    MemData,
+    Type,
+    Error,
+};
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum CharOp {
+    IsAlphabetic,
+    IsNumeric,
+    IsWhitespace,
+    /// Chars without a single-char uppercase form are left as is
+    Upcase,
+    /// Chars without a single-char lowercase form are left as is
+    Downcase,
+}
+
+/// Every char operation, indexed by its quantifier
+pub const CHAR_OPS: &[CharOp] = &[
+    CharOp::IsAlphabetic, CharOp::IsNumeric, CharOp::IsWhitespace, CharOp::Upcase,
+    CharOp::Downcase,
+];
+
+impl CharOp {
+    pub fn from_quantif(n: u32) -> Option<CharOp> {
+        CHAR_OPS.get(n as usize).cloned()
+    }
+
+    pub fn quantif(self) -> u32 {
+        CHAR_OPS.iter().position(|op| *op == self).unwrap() as u32
+    }
+
+    pub fn run(self, v: &MemData) -> Result<MemData, Error> {
+        let c = match *v.deref() {
+            MemData::Char(c) => c,
+            ref v => return Err(v.wrong_type(Type::Char)),
+        };
+        Ok(match self {
+            CharOp::IsAlphabetic => MemData::Bool(c.is_alphabetic()),
+            CharOp::IsNumeric => MemData::Bool(c.is_numeric()),
+            CharOp::IsWhitespace => MemData::Bool(c.is_whitespace()),
+            CharOp::Upcase => MemData::Char(single(c.to_uppercase()).unwrap_or(c)),
+            CharOp::Downcase => MemData::Char(single(c.to_lowercase()).unwrap_or(c)),
+        })
+    }
+}
+
+fn single<I: Iterator<Item = char>>(mut it: I) -> Option<char> {
+    match (it.next(), it.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
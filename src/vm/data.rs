@@ -1,12 +1,19 @@
 use super::{
     Error,
     Environment,
+    BigInt,
+    Symbol,
+    Map,
+    Cons,
+    RecordType,
+    Record,
 };
 
 use std::fmt;
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::rc::Rc;
+use std::cell::RefCell;
 
 pub type ConstID = u16;
 pub type IdentID = u16;
@@ -21,7 +28,6 @@ pub enum OpCode {
      REC,
      LMB,
      PRC,
-     // DFN,
      DVR,
      LVR,
      IFT,
@@ -41,9 +47,52 @@ pub enum OpCode {
      MUL,
      DIV,
      DSP,
+     CAN,
+     COR,
+     RRR,
+     DFN,
+     CAP,
+     VEC,
+     VRF,
+     VST,
+     VLN,
+     VSL,
+     MAP,
+     MGT,
+     MST,
+     MDL,
+     MKS,
+     MLN,
+     SCA,
+     SCD,
+     SET,
+     STR,
+     CHR,
+     CEI,
+     CEV,
+     CES,
+     RTD,
+     RNW,
+     RIS,
+     RGT,
+     RST,
 }
 
-// TODO: make Op compact and outputtable
+/// Every opcode, indexed by its numeric value
+pub const OPCODES: &[OpCode] = &[
+    OpCode::PSS, OpCode::PPS, OpCode::REC, OpCode::LMB, OpCode::PRC,
+    OpCode::DVR, OpCode::LVR, OpCode::IFT, OpCode::IFE, OpCode::CGT,
+    OpCode::CLT, OpCode::CEQ, OpCode::CNT, OpCode::CLL, OpCode::CNV,
+    OpCode::CAT, OpCode::CNS, OpCode::CAR, OpCode::CDR, OpCode::ADD,
+    OpCode::SUB, OpCode::MUL, OpCode::DIV, OpCode::DSP, OpCode::CAN,
+    OpCode::COR, OpCode::RRR, OpCode::DFN, OpCode::CAP, OpCode::VEC,
+    OpCode::VRF, OpCode::VST, OpCode::VLN, OpCode::VSL, OpCode::MAP,
+    OpCode::MGT, OpCode::MST, OpCode::MDL, OpCode::MKS, OpCode::MLN,
+    OpCode::SCA, OpCode::SCD, OpCode::SET, OpCode::STR,
+    OpCode::CHR, OpCode::CEI, OpCode::CEV, OpCode::CES, OpCode::RTD, OpCode::RNW,
+    OpCode::RIS, OpCode::RGT, OpCode::RST,
+];
+
 #[derive(PartialEq, Eq, Clone)]
 pub struct Op {
     pub opcode: OpCode, // u6
@@ -63,13 +112,27 @@ pub enum Type {
     Proc,
     Inst,
     Str,
+    Symbol,
     Pair,
     Int,
     Char,
     Bool,
     Nil,
+    Float,
+    BigInt,
+    Vector,
+    Map,
+    RecordType,
+    Record,
 }
 
+/// Every type, indexed by its numeric value
+pub const TYPES: &[Type] = &[
+    Type::Pointer, Type::Lambda, Type::Proc, Type::Inst, Type::Str,
+    Type::Symbol, Type::Pair, Type::Int, Type::Char, Type::Bool, Type::Nil,
+    Type::Float, Type::BigInt, Type::Vector, Type::Map, Type::RecordType, Type::Record,
+];
+
 // NOTE: Keep this as small as possible
 // pub enum ConstData {
 //     Inst(Op),
@@ -81,8 +144,9 @@ pub enum Type {
 //     Nil,
 // }
 
+/// `==` compares contents, see `equal.rs`
 #[allow(dead_code)]
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub enum MemData {
     // Lambda(Procedure, Stack),
     Pointer(Rc<MemData>),
@@ -90,15 +154,27 @@ pub enum MemData {
     Proc(Procedure),
     Inst(Op),
     Str(String),
-    Pair { car: Box<MemData>, cdr: Box<MemData>},
-    Int(u32),
-    Char(u8),
+    Symbol(Symbol),
+    Pair(Cons),
+    Int(i64),
+    /// A Unicode scalar value
+    Char(char),
     Bool(bool),
-    Nil, }
-
-#[derive(PartialEq, Eq, Debug, Clone)]
+    Nil,
+    Float(f64),
+    BigInt(BigInt),
+    /// Shared: every clone refers to the same, mutable, items
+    Vector(Rc<RefCell<Vec<MemData>>>),
+    /// Shared like `Vector`
+    Map(Rc<RefCell<Map>>),
+    RecordType(RecordType),
+    Record(Record), }
+
+#[derive(Debug, Clone)]
 pub struct Procedure {
-    insts: Vec<Op>
+    insts: Vec<Op>,
+    /// The variable it was first defined as, for printing only
+    name: Option<Symbol>,
 }
 
 pub struct Bin {
@@ -129,12 +205,12 @@ impl Op {
         }
     }
 
-    // NOTE: ConstID + ofs > u16 = undefined behaviour!!!
-    pub fn apply_const_offset(&mut self, ofs: usize) {
-        self.val = self.val.map(|cid| cid + ofs as u16); // TODO: make offset actually be able to be usize
+    /// Replaces every const id `i` by `new[i]`
+    pub fn apply_const_map(&mut self, new: &[ConstID]) {
+        self.val = self.val.map(|cid| new.get(cid as usize).cloned().unwrap_or(cid));
     }
 
-    // old and new must be sorted!!
+    // old must be sorted and new ordered to match it!!
     pub fn apply_ident_swap(&mut self, old: &Vec<IdentID>, new: &Vec<IdentID>) {
         self.ident = self.ident.map(|iid| {
             old.binary_search(&iid).map(|i| *new.get(i).unwrap()).unwrap_or(iid)
@@ -142,6 +218,18 @@ impl Op {
     }
 }
 
+impl OpCode {
+    pub fn from_u8(b: u8) -> Option<OpCode> {
+        OPCODES.get(b as usize).cloned()
+    }
+}
+
+impl Type {
+    pub fn from_u8(b: u8) -> Option<Type> {
+        TYPES.get(b as usize).cloned()
+    }
+}
+
 impl fmt::Debug for Op {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "Op(\"{:?}{}{}{}{}{}\")",
@@ -163,17 +251,24 @@ impl MemData {
             MemData::Proc(..)  => Type::Proc,
             MemData::Inst(..)  => Type::Inst,
             MemData::Str(..)   => Type::Str,
-            MemData::Pair {..} => Type::Pair,
+            MemData::Symbol(..) => Type::Symbol,
+            MemData::Pair(..)  => Type::Pair,
             MemData::Int(..)   => Type::Int,
             MemData::Char(..)  => Type::Char,
             MemData::Bool(..)  => Type::Bool,
             MemData::Nil       => Type::Nil,
+            MemData::Float(..) => Type::Float,
+            MemData::BigInt(..) => Type::BigInt,
+            MemData::Vector(..) => Type::Vector,
+            MemData::Map(..)    => Type::Map,
+            MemData::RecordType(..) => Type::RecordType,
+            MemData::Record(..) => Type::Record,
         }
     }
 
     #[inline]
     pub fn wrong_type(&self, wanted: Type) -> Error {
-        Error::TypeError(wanted, self.get_type())
+        Error::TypeError(wanted, self.get_type(), None)
     }
 
     /// Traces pointer type back to the source and returns its MemData value
@@ -186,6 +281,49 @@ impl MemData {
         }
     }
 
+    pub fn cons(car: MemData, cdr: MemData) -> MemData {
+        MemData::Pair(Cons::new(car, cdr))
+    }
+
+    pub fn vector(items: Vec<MemData>) -> MemData {
+        MemData::Vector(Rc::new(RefCell::new(items)))
+    }
+
+    /// Reads an `Int` as an index into `len` items, or as a bound of a slice
+    /// of them if `bound` is set
+    pub fn as_index(&self, len: usize, bound: bool) -> Result<usize, Error> {
+        let i = match *self.deref() {
+            MemData::Int(i) => i,
+            ref v => return Err(v.wrong_type(Type::Int)),
+        };
+        let limit = if bound { len as u64 + 1 } else { len as u64 };
+        if i < 0 || i as u64 >= limit {
+            return Err(Error::IndexOutOfRange(i, len));
+        }
+        Ok(i as usize)
+    }
+
+    /// A proper list of `items`
+    pub fn list(items: Vec<MemData>) -> MemData {
+        items.into_iter().rev().fold(MemData::Nil, |l, v| MemData::cons(v, l))
+    }
+
+    /// Collects the elements of a proper list, or `None` if `self` isn't one
+    pub fn list_items(&self) -> Option<Vec<MemData>> {
+        let mut items = Vec::new();
+        let mut cur = self.deref().clone();
+        loop {
+            cur = match cur {
+                MemData::Nil => return Some(items),
+                MemData::Pair(ref c) => {
+                    items.push(c.car().deref().clone());
+                    c.cdr().deref().clone()
+                },
+                _ => return None,
+            }
+        }
+    }
+
     pub fn create_pointer(data: MemData) -> MemData {
         if data.get_type() == Type::Pointer {
             data
@@ -269,11 +407,45 @@ impl MemData {
         }
     }
 
+    /// The value of a number as a float
+    pub fn as_f64(&self) -> Option<f64> {
+        match *self.deref() {
+            MemData::Int(i)   => Some(i as f64),
+            MemData::Float(f) => Some(f),
+            MemData::BigInt(ref b) => Some(b.to_f64()),
+            _ => None,
+        }
+    }
+
+    /// An integer result, demoted to `Int` when it fits
+    pub fn integer(b: BigInt) -> MemData {
+        match b.to_i64() {
+            Some(i) => MemData::Int(i),
+            None => MemData::BigInt(b),
+        }
+    }
+
     pub fn cmp(&self, other: &Self) -> Result<Ordering, Error> {
         match (self.deref(), other.deref()) {
         (&MemData::Int(ref s), &MemData::Int(ref o)) => 
             Ok(s.cmp(o)),
 
+        (a, b) if a.as_f64().is_some() && b.as_f64().is_some() => {
+            match Numbers::of("ordering", a, b)? {
+                Numbers::Ints(a, b) => Ok(a.cmp(&b)),
+                Numbers::Bigs(a, b) => Ok(a.cmp(&b)),
+                // NaN isn't ordered against anything
+                Numbers::Floats(a, b) => a.partial_cmp(&b)
+                    .ok_or(Error::BadOperandTypes("ordering", Type::Float, Type::Float)),
+            }
+        },
+
+        (MemData::Str(s), MemData::Str(o)) =>
+            Ok(s.cmp(o)),
+
+        (&MemData::Char(s), &MemData::Char(o)) =>
+            Ok(s.cmp(&o)),
+
         (&MemData::Nil, &MemData::Nil) =>
             Ok(Ordering::Equal),
 
@@ -290,28 +462,116 @@ impl MemData {
         self.cmp(other).map(|v| v == Ordering::Less)
     }
 
+    /// `=`: numbers are equal across types, like `(= 1 1.0)`, anything else is
+    /// compared structurally
     pub fn eq(&self, other: &Self) -> Result<bool, Error> {
+        match (self.as_f64(), other.as_f64()) {
+            (Some(_), Some(_)) => Ok(self.cmp(other).ok() == Some(Ordering::Equal)),
+            _ => Ok(self.is_equal(other)),
+        }
+    }
+
+    /// `eq?`: pairs, vectors, maps and records are the same only if they are the same
+    /// object. Other values are immutable and copied around, so they are the
+    /// same when they have the same type and representation (floats bitwise)
+    pub fn is_eq(&self, other: &Self) -> bool {
         match (self.deref(), other.deref()) {
-            (&MemData::Str(ref s), &MemData::Str(ref o)) =>
-                Ok(s == o),
+            (MemData::Pair(a), MemData::Pair(b)) => a.ptr_eq(b),
+            (MemData::Vector(a), MemData::Vector(b)) => Rc::ptr_eq(a, b),
+            (MemData::Map(a), MemData::Map(b)) => Rc::ptr_eq(a, b),
+            (MemData::Record(a), MemData::Record(b)) => a.ptr_eq(b),
+            (&MemData::Float(a), &MemData::Float(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        }
+    }
 
-            _ => self.cmp(other).map(|v| v == Ordering::Equal),
+    /// `eqv?`: like `eq?`, except that floats are compared by value, so
+    /// `0.0` and `-0.0` are eqv but NaN isn't eqv to itself
+    pub fn is_eqv(&self, other: &Self) -> bool {
+        match (self.deref(), other.deref()) {
+            (&MemData::Float(a), &MemData::Float(b)) => a == b,
+            (a, b) => a.is_eq(b),
         }
     }
 
+    /// Converts to `typ`, see CNV in spec.md for the supported pairs
     pub fn convert(&self, typ: &Type) -> Result<Self, Error> {
-        match *typ {
-            Type::Str => {
-                Ok(MemData::Str(
-                    match *self {
-                        MemData::Int(i) => format!("{:?}", i),
-                        _ => format!("{:?}", self),
-                    }))
-            },
-            _ => {
-                Err(Error::IllegalConversion(self.get_type(), typ.clone()))
-            }
+        let v = self.deref();
+        if v.get_type() == *typ {
+            return Ok(v.clone());
         }
+        let illegal = || Error::IllegalConversion(v.get_type(), *typ);
+
+        Ok(match (v, *typ) {
+            (&MemData::Int(i), Type::Str)        => MemData::Str(i.to_string()),
+            (MemData::BigInt(b), Type::Str)      => MemData::Str(b.to_string()),
+            (&MemData::Float(f), Type::Str)      => MemData::Str(format!("{:?}", f)),
+            (&MemData::Char(c), Type::Str)       => MemData::Str(c.to_string()),
+            (&MemData::Bool(b), Type::Str)       => MemData::Str(if b { "#t" } else { "#f" }.to_owned()),
+            (&MemData::Symbol(s), Type::Str)     => MemData::Str(s.as_str().to_owned()),
+            // A list of chars
+            (&MemData::Nil, Type::Str) | (&MemData::Pair(..), Type::Str) => {
+                let mut s = String::new();
+                for c in v.list_items().ok_or_else(illegal)? {
+                    match *c.deref() {
+                        MemData::Char(c) => s.push(c),
+                        ref c => return Err(c.wrong_type(Type::Char)),
+                    }
+                }
+                MemData::Str(s)
+            },
+
+            (MemData::Str(s), Type::Int) => {
+                let t = s.trim();
+                t.parse::<i64>().map(MemData::Int)
+                    .or_else(|_| t.parse::<BigInt>().map(MemData::integer))
+                    .map_err(|_| Error::ParseError(Type::Int, s.clone()))?
+            },
+            (MemData::Str(s), Type::Pair) => MemData::list(s.chars().map(MemData::Char).collect()),
+            (MemData::Str(s), Type::Symbol) => MemData::Symbol(Symbol::intern(s)),
+
+            // Surrogates and values past 0x10ffff aren't chars
+            (&MemData::Int(i), Type::Char) if (0..=0x10ffff).contains(&i) =>
+                MemData::Char(char::from_u32(i as u32).ok_or_else(illegal)?),
+            (&MemData::Char(c), Type::Int) => MemData::Int(c as i64),
+
+            (&MemData::Int(..), Type::Float) | (&MemData::BigInt(..), Type::Float) =>
+                MemData::Float(v.as_f64().unwrap()),
+            // Truncates towards zero
+            (&MemData::Float(f), Type::Int) if f.is_finite() && f.abs() < (1u64 << 63) as f64 =>
+                MemData::Int(f as i64),
+
+            _ => return Err(illegal()),
+        })
+    }
+}
+
+/// Operands of an arithmetic operation, promoted to a common numeric type
+enum Numbers {
+    Ints(i64, i64),
+    Bigs(BigInt, BigInt),
+    Floats(f64, f64),
+}
+
+impl Numbers {
+    fn of(op: &'static str, a: &MemData, b: &MemData) -> Result<Numbers, Error> {
+        Ok(match (a.deref(), b.deref()) {
+            (&MemData::Int(a),   &MemData::Int(b))   => Numbers::Ints(a, b),
+            (&MemData::Int(a),   &MemData::Float(b)) => Numbers::Floats(a as f64, b),
+            (&MemData::Float(a), &MemData::Int(b))   => Numbers::Floats(a, b as f64),
+            (&MemData::Float(a), &MemData::Float(b)) => Numbers::Floats(a, b),
+            (MemData::BigInt(a), MemData::BigInt(b)) => Numbers::Bigs(a.clone(), b.clone()),
+            (MemData::BigInt(a), MemData::Int(b)) => Numbers::Bigs(a.clone(), BigInt::from_i64(*b)),
+            (MemData::Int(a), MemData::BigInt(b)) => Numbers::Bigs(BigInt::from_i64(*a), b.clone()),
+            (a @ &MemData::BigInt(..), &MemData::Float(b)) => Numbers::Floats(a.as_f64().unwrap(), b),
+            (&MemData::Float(a), b @ &MemData::BigInt(..)) => Numbers::Floats(a, b.as_f64().unwrap()),
+            (a, b) => return Err(Error::BadOperandTypes(op, a.get_type(), b.get_type())),
+        })
+    }
+
+    /// Redoes an `i64` operation that overflowed on bignums
+    fn promoted(a: i64, b: i64, f: fn(&BigInt, &BigInt) -> BigInt) -> MemData {
+        MemData::integer(f(&BigInt::from_i64(a), &BigInt::from_i64(b)))
     }
 }
 
@@ -319,16 +579,12 @@ impl ::std::ops::Add for MemData {
     type Output = Result<MemData, Error>;
 
     fn add(self, other: Self) -> Self::Output {
-        let (a, b) = (self.deref().get_type(), other.deref().get_type());
-
-        // We're dissallowing inter-type operations for now at least
-        if a != b { return Err(Error::BadOperandTypes("sum", a, b)) }
-
-        if let (&MemData::Int(a), &MemData::Int(b)) = (self.deref(), other.deref()) {
-            return Ok(MemData::Int(a + b))
+        match Numbers::of("sum", &self, &other)? {
+            Numbers::Ints(a, b) => Ok(a.checked_add(b).map(MemData::Int)
+                .unwrap_or_else(|| Numbers::promoted(a, b, |a, b| a + b))),
+            Numbers::Bigs(a, b) => Ok(MemData::integer(&a + &b)),
+            Numbers::Floats(a, b) => Ok(MemData::Float(a + b)),
         }
-
-        Err(Error::BadOperandTypes("sum", a, b))
     }
 }
 
@@ -336,16 +592,12 @@ impl ::std::ops::Sub for MemData {
     type Output = Result<MemData, Error>;
 
     fn sub(self, other: Self) -> Self::Output {
-        let (a, b) = (self.deref().get_type(), other.deref().get_type());
-
-        // We're dissallowing inter-type operations for now at least
-        if a != b { return Err(Error::BadOperandTypes("subtraction", a, b)) }
-
-        if let (&MemData::Int(a), &MemData::Int(b)) = (self.deref(), other.deref()) {
-            return Ok(MemData::Int(a - b))
+        match Numbers::of("subtraction", &self, &other)? {
+            Numbers::Ints(a, b) => Ok(a.checked_sub(b).map(MemData::Int)
+                .unwrap_or_else(|| Numbers::promoted(a, b, |a, b| a - b))),
+            Numbers::Bigs(a, b) => Ok(MemData::integer(&a - &b)),
+            Numbers::Floats(a, b) => Ok(MemData::Float(a - b)),
         }
-
-        Err(Error::BadOperandTypes("subtraction", a, b))
     }
 }
 
@@ -353,16 +605,12 @@ impl ::std::ops::Mul for MemData {
     type Output = Result<MemData, Error>;
 
     fn mul(self, other: Self) -> Self::Output {
-        let (a, b) = (self.deref().get_type(), other.deref().get_type());
-
-        // We're dissallowing inter-type operations for now at least
-        if a != b { return Err(Error::BadOperandTypes("multiplication", a, b)) }
-
-        if let (&MemData::Int(a), &MemData::Int(b)) = (self.deref(), other.deref()) {
-            return Ok(MemData::Int(a * b))
+        match Numbers::of("multiplication", &self, &other)? {
+            Numbers::Ints(a, b) => Ok(a.checked_mul(b).map(MemData::Int)
+                .unwrap_or_else(|| Numbers::promoted(a, b, |a, b| a * b))),
+            Numbers::Bigs(a, b) => Ok(MemData::integer(&a * &b)),
+            Numbers::Floats(a, b) => Ok(MemData::Float(a * b)),
         }
-
-        Err(Error::BadOperandTypes("multiplication", a, b))
     }
 }
 
@@ -370,46 +618,137 @@ impl ::std::ops::Div for MemData {
     type Output = Result<MemData, Error>;
 
     fn div(self, other: Self) -> Self::Output {
-        let (a, b) = (self.deref().get_type(), other.deref().get_type());
+        match Numbers::of("division", &self, &other)? {
+            Numbers::Ints(_, 0) => Err(Error::DivisionByZero),
+            // Only `i64::MIN / -1` overflows
+            Numbers::Ints(a, b) => Ok(a.checked_div(b).map(MemData::Int)
+                .unwrap_or_else(|| Numbers::promoted(a, b, |a, b| a / b))),
+            Numbers::Bigs(_, ref b) if b.is_zero() => Err(Error::DivisionByZero),
+            Numbers::Bigs(a, b) => Ok(MemData::integer(&a / &b)),
+            // Floats follow IEEE 754: dividing by zero gives an infinity
+            Numbers::Floats(a, b) => Ok(MemData::Float(a / b)),
+        }
+    }
+}
 
-        // We're dissallowing inter-type operations for now at least
-        if a != b { return Err(Error::BadOperandTypes("division", a, b)) }
+/// Where the idents and consts of a bin went when it was loaded
+pub struct Relocation {
+    old_idents: Vec<IdentID>,
+    new_idents: Vec<IdentID>,
+    consts: Vec<ConstID>,
+}
 
-        if let (&MemData::Int(a), &MemData::Int(b)) = (self.deref(), other.deref()) {
-            return Ok(MemData::Int(a / b))
-        }
+impl Relocation {
+    /// `new_idents[i]` replaces `old_idents[i]`, `consts[i]` replaces const `i`
+    pub fn new(old_idents: Vec<IdentID>, new_idents: Vec<IdentID>, consts: Vec<ConstID>) -> Self {
+        // Sort the pairs together: reused ids don't preserve the order of the old ones
+        let mut swaps: Vec<(IdentID, IdentID)> = old_idents.into_iter().zip(new_idents).collect();
+        swaps.sort_unstable_by_key(|&(o, _)| o);
+        let (old_idents, new_idents) = swaps.into_iter().unzip();
+        Self { old_idents, new_idents, consts }
+    }
 
-        Err(Error::BadOperandTypes("division", a, b))
+    /// Where const `i` of the bin went
+    pub fn const_id(&self, i: usize) -> ConstID {
+        self.consts[i]
     }
-}
 
-impl Procedure {
-    pub fn apply_const_offset(&mut self, ofs: usize) {
-        self.insts.iter_mut().for_each(|i: &mut Op| i.apply_const_offset(ofs));
+    pub fn apply(&self, op: &mut Op) {
+        op.apply_ident_swap(&self.old_idents, &self.new_idents);
+        op.apply_const_map(&self.consts);
     }
 
-    pub fn apply_ident_swaps(&mut self, mut old: Vec<IdentID>, mut new: Vec<IdentID>) {
-        old.sort_unstable();
-        new.sort_unstable();
-        self.insts.iter_mut().for_each(|i: &mut Op| i.apply_ident_swap(&old, &new))
+    /// Relocates the ops of the procedures and instructions found in a constant
+    pub fn apply_data(&self, v: &mut MemData) {
+        match *v {
+            MemData::Inst(ref mut op) => self.apply(op),
+            MemData::Proc(ref mut p) => p.relocate(self),
+            MemData::Pair(ref c) => {
+                // Lists are walked in a loop so long ones don't nest calls
+                let mut c = c.clone();
+                loop {
+                    let mut car = c.car();
+                    self.apply_data(&mut car);
+                    c.set_car(car);
+
+                    let mut cdr = c.cdr();
+                    c = match cdr {
+                        MemData::Pair(next) => next,
+                        _ => {
+                            self.apply_data(&mut cdr);
+                            c.set_cdr(cdr);
+                            break;
+                        },
+                    };
+                }
+            },
+            _ => (),
+        }
+    }
+}
+
+impl Procedure {
+    pub fn relocate(&mut self, r: &Relocation) {
+        self.insts.iter_mut().for_each(|i: &mut Op| r.apply(i));
     }
 
     pub fn iter(&self) -> ::std::slice::Iter<Op> {
         self.insts.iter()
     }
+
+    pub fn len(&self) -> usize {
+        self.insts.len()
+    }
+
+    pub fn as_slice(&self) -> &[Op] {
+        &self.insts
+    }
+
+    pub fn name(&self) -> Option<Symbol> {
+        self.name
+    }
 }
 
+/// The name doesn't take part: it is only a label
+impl PartialEq for Procedure {
+    fn eq(&self, other: &Self) -> bool {
+        self.insts == other.insts
+    }
+}
+
+impl Eq for Procedure {}
+
 impl ::std::iter::FromIterator<Op> for Procedure {
     fn from_iter<I: IntoIterator<Item=Op>>(iter: I) -> Self {
         Self {
-            insts: iter.into_iter().collect()
+            insts: iter.into_iter().collect(),
+            name: None,
         }
     }
 }
 
 impl From<Vec<Op>> for Procedure {
     fn from(insts: Vec<Op>) -> Self {
-        Self { insts }
+        Self { insts, name: None }
+    }
+}
+
+impl MemData {
+    /// Names an anonymous procedure or lambda after the variable it is being
+    /// defined as; anything else is returned as is
+    pub fn named(self, name: Symbol) -> MemData {
+        match self {
+            MemData::Proc(ref p) | MemData::Lambda(ref p, _) if p.name.is_some() => self,
+            MemData::Proc(mut p) => {
+                p.name = Some(name);
+                MemData::Proc(p)
+            },
+            MemData::Lambda(mut p, env) => {
+                p.name = Some(name);
+                MemData::Lambda(p, env)
+            },
+            v => v,
+        }
     }
 }
 
@@ -426,6 +765,22 @@ impl Bin {
         }
     }
 
+    pub fn insts(&self) -> &Procedure {
+        &self.insts
+    }
+
+    pub fn idents(&self) -> &[IdentID] {
+        &self.idents
+    }
+
+    pub fn var_strings(&self) -> &HashMap<IdentID, String> {
+        &self.var_strings
+    }
+
+    pub fn consts(&self) -> &[MemData] {
+        &self.consts
+    }
+
     pub fn unpack(self) -> (Procedure,
                             Vec<IdentID>,
                             HashMap<IdentID, String>,
@@ -0,0 +1,176 @@
+//! Packed `u32` encoding of `Op`s (see "OPERATION" in spec.md)
+//!
+//!     [6bit OP][18bit n/ident][8bit flags] + optional[multi]
+//!
+//! Extension words follow the first one in this order, each only if its flag is set:
+//!
+//!     SECOND: [18bit ident][14bit ---]   (the op has both an n and an ident)
+//!     CONST:  [32bit const]              (reg/const flag of DVR, LVR, ...)
+//!     TYPE:   [16bit type][16bit ---]
+
+use super::{
+    Op,
+    OpCode,
+    Type,
+    Procedure,
+    IdentID,
+    ConstID,
+    Quantif,
+    Error,
+};
+
+const FIELD_BITS: u32 = 18;
+const FIELD_MAX: u32 = (1 << FIELD_BITS) - 1;
+const OPCODE_MAX: u8 = (1 << 6) - 1;
+
+bitflags! {
+    pub struct OpFlags: u8 {
+        /// At least one extension word follows
+        const EXPANSION = 0b00000001;
+        /// The 18 bit field holds an ident rather than an n
+        const IDENT     = 0b00000010;
+        const MUTE      = 0b00000100;
+        /// The 18 bit field is in use at all
+        const FIELD     = 0b00001000;
+        const SECOND    = 0b00010000;
+        const CONST     = 0b00100000;
+        const TYPE      = 0b01000000;
+    }
+}
+
+impl Op {
+    /// Appends the packed form of `self` to `out`
+    pub fn encode(&self, out: &mut Vec<u32>) -> Result<(), Error> {
+        let opcode = self.opcode.clone() as u8;
+        debug_assert!(opcode <= OPCODE_MAX);
+
+        let mut flags = OpFlags::empty();
+        let mut ext = Vec::new();
+
+        let field = match (self.n, self.ident) {
+            (Some(n), ident) => {
+                if n > FIELD_MAX {
+                    return Err(Error::EncodingOverflow("n", n));
+                }
+                if let Some(i) = ident {
+                    flags |= OpFlags::SECOND;
+                    ext.push((i as u32) << (32 - FIELD_BITS));
+                }
+                flags |= OpFlags::FIELD;
+                n
+            },
+            (None, Some(i)) => {
+                flags |= OpFlags::FIELD | OpFlags::IDENT;
+                i as u32
+            },
+            (None, None) => 0,
+        };
+
+        if let Some(c) = self.val {
+            flags |= OpFlags::CONST;
+            ext.push(c as u32);
+        }
+        if let Some(t) = self.typ {
+            flags |= OpFlags::TYPE;
+            ext.push((t as u32) << 16);
+        }
+        if self.mute {
+            flags |= OpFlags::MUTE;
+        }
+        if !ext.is_empty() {
+            flags |= OpFlags::EXPANSION;
+        }
+
+        out.push((opcode as u32) << 26 | field << 8 | flags.bits() as u32);
+        out.append(&mut ext);
+        Ok(())
+    }
+
+    /// Decodes one op from the start of `words`, returning it with the number of words used
+    pub fn decode(words: &[u32]) -> Result<(Op, usize), Error> {
+        let first = *words.first().ok_or(Error::BadEncoding("missing operation"))?;
+
+        let opcode = OpCode::from_u8((first >> 26) as u8)
+            .ok_or(Error::BadEncoding("unknown opcode"))?;
+        let field = (first >> 8) & FIELD_MAX;
+        let flags = OpFlags::from_bits(first as u8)
+            .ok_or(Error::BadEncoding("reserved flag bits set"))?;
+
+        let has_ext = flags.intersects(OpFlags::SECOND | OpFlags::CONST | OpFlags::TYPE);
+        if has_ext != flags.contains(OpFlags::EXPANSION) {
+            return Err(Error::BadEncoding("expansion flag disagrees with the extension words"));
+        }
+        if flags.contains(OpFlags::SECOND) && flags.contains(OpFlags::IDENT) {
+            return Err(Error::BadEncoding("second ident word with an ident in the field"));
+        }
+
+        let mut used = 1;
+        let mut next = || {
+            let w = words.get(used).cloned().ok_or(Error::BadEncoding("missing extension word"));
+            used += 1;
+            w
+        };
+
+        let in_field = flags.contains(OpFlags::FIELD);
+        let (mut ident, n) = if flags.contains(OpFlags::IDENT) {
+            if !in_field {
+                return Err(Error::BadEncoding("ident flag without a field"));
+            }
+            (Some(ident_from(field)?), None)
+        } else {
+            (None, if in_field { Some(field as Quantif) } else { None })
+        };
+
+        if flags.contains(OpFlags::SECOND) {
+            ident = Some(ident_from(next()? >> (32 - FIELD_BITS))?);
+        }
+        let val = if flags.contains(OpFlags::CONST) {
+            let c = next()?;
+            if c > ConstID::MAX as u32 {
+                return Err(Error::EncodingOverflow("const", c));
+            }
+            Some(c as ConstID)
+        } else {
+            None
+        };
+        let typ = if flags.contains(OpFlags::TYPE) {
+            let t = next()? >> 16;
+            Some(Type::from_u8(t as u8)
+                 .filter(|_| t <= 0xff)
+                 .ok_or(Error::BadEncoding("unknown type"))?)
+        } else {
+            None
+        };
+
+        let op = Op::new(opcode, ident, n, val, typ, flags.contains(OpFlags::MUTE));
+        Ok((op, used))
+    }
+}
+
+fn ident_from(v: u32) -> Result<IdentID, Error> {
+    if v > IdentID::MAX as u32 {
+        Err(Error::EncodingOverflow("ident", v))
+    } else {
+        Ok(v as IdentID)
+    }
+}
+
+impl Procedure {
+    pub fn encode(&self) -> Result<Vec<u32>, Error> {
+        let mut out = Vec::with_capacity(self.len());
+        for op in self.iter() {
+            op.encode(&mut out)?;
+        }
+        Ok(out)
+    }
+
+    pub fn decode(mut words: &[u32]) -> Result<Procedure, Error> {
+        let mut ops = Vec::new();
+        while !words.is_empty() {
+            let (op, used) = Op::decode(words)?;
+            ops.push(op);
+            words = &words[used..];
+        }
+        Ok(ops.into())
+    }
+}
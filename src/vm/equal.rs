@@ -0,0 +1,135 @@
+//! Structural comparison of values that may contain themselves
+//!
+//! Both values are walked in step. A pair of shared objects (pairs, vectors,
+//! maps, records) met again while it is still being compared is taken to be
+//! equal: if the two differ somewhere, the comparison already under way finds
+//! it. So every pair of objects is compared at most once, cycles included.
+
+use super::{
+    Cons,
+    MemData,
+};
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+enum Mode {
+    /// `equal?`: leaves and records are compared with `eqv?`
+    Equal,
+    /// `==` on `MemData`: every variant by its contents, records included
+    Data,
+}
+
+struct Comparison {
+    mode: Mode,
+    /// Pairs of objects being compared, or compared already. Only made once
+    /// needed: most comparisons are between leaves
+    seen: Option<HashSet<(usize, usize)>>,
+}
+
+impl MemData {
+    /// `equal?`: recurses through pairs, vectors and maps, comparing the
+    /// leaves with `eqv?`
+    pub fn is_equal(&self, other: &Self) -> bool {
+        Comparison { mode: Mode::Equal, seen: None }.values(self, other)
+    }
+}
+
+impl PartialEq for MemData {
+    fn eq(&self, other: &Self) -> bool {
+        Comparison { mode: Mode::Data, seen: None }.values(self, other)
+    }
+}
+
+impl Comparison {
+    /// Whether `a` and `b` still need to be compared
+    fn enter(&mut self, a: *const (), b: *const ()) -> bool {
+        a != b && self.seen.get_or_insert_with(HashSet::new).insert((a as usize, b as usize))
+    }
+
+    /// What is compared in place of `v`: `equal?` looks through pointers
+    fn view<'v>(&self, v: &'v MemData) -> &'v MemData {
+        match self.mode {
+            Mode::Equal => v.deref(),
+            Mode::Data => v,
+        }
+    }
+
+    fn values(&mut self, a: &MemData, b: &MemData) -> bool {
+        match (self.view(a), self.view(b)) {
+            (MemData::Pair(ca), MemData::Pair(cb)) => self.lists(ca.clone(), cb.clone()),
+            (MemData::Vector(va), MemData::Vector(vb)) => {
+                if !self.enter(Rc::as_ptr(va) as *const (), Rc::as_ptr(vb) as *const ()) {
+                    return true;
+                }
+                let (va, vb) = (va.borrow(), vb.borrow());
+                va.len() == vb.len()
+                    && va.iter().zip(vb.iter()).all(|(x, y)| self.values(x, y))
+            },
+            (MemData::Map(ma), MemData::Map(mb)) => {
+                if !self.enter(Rc::as_ptr(ma) as *const (), Rc::as_ptr(mb) as *const ()) {
+                    return true;
+                }
+                let (ma, mb) = (ma.borrow(), mb.borrow());
+                ma.len() == mb.len()
+                    && ma.iter().all(|(k, v)| mb.get(k).is_some_and(|o| self.values(v, o)))
+            },
+            (MemData::Record(ra), MemData::Record(rb)) if self.mode == Mode::Data => {
+                if !ra.rtd().ptr_eq(rb.rtd()) {
+                    return false;
+                }
+                if !self.enter(ra.as_ptr(), rb.as_ptr()) {
+                    return true;
+                }
+                let (sa, sb) = (ra.slots(), rb.slots());
+                sa.len() == sb.len()
+                    && sa.iter().zip(sb.iter()).all(|(x, y)| self.values(x, y))
+            },
+            (a, b) => self.leaves(a, b),
+        }
+    }
+
+    /// Walks down the cdrs in a loop so long lists don't nest calls
+    fn lists(&mut self, mut a: Cons, mut b: Cons) -> bool {
+        loop {
+            if !self.enter(a.as_ptr(), b.as_ptr()) {
+                return true;
+            }
+            if !self.values(&a.car(), &b.car()) {
+                return false;
+            }
+            let (da, db) = (a.cdr(), b.cdr());
+            match (self.view(&da), self.view(&db)) {
+                (MemData::Pair(x), MemData::Pair(y)) => {
+                    a = x.clone();
+                    b = y.clone();
+                },
+                (x, y) => return self.values(x, y),
+            }
+        }
+    }
+
+    /// Values that don't hold other values, which `values` looks into itself
+    fn leaves(&mut self, a: &MemData, b: &MemData) -> bool {
+        if self.mode == Mode::Equal {
+            return a.is_eqv(b);
+        }
+        match (a, b) {
+            (MemData::Pointer(a), MemData::Pointer(b)) => self.values(a, b),
+            (MemData::Lambda(p, e), MemData::Lambda(q, f)) => p == q && e == f,
+            (MemData::Proc(p), MemData::Proc(q)) => p == q,
+            (MemData::Inst(a), MemData::Inst(b)) => a == b,
+            (MemData::Str(a), MemData::Str(b)) => a == b,
+            (MemData::Symbol(a), MemData::Symbol(b)) => a == b,
+            (MemData::Int(a), MemData::Int(b)) => a == b,
+            (MemData::Char(a), MemData::Char(b)) => a == b,
+            (MemData::Bool(a), MemData::Bool(b)) => a == b,
+            (MemData::Nil, MemData::Nil) => true,
+            (MemData::Float(a), MemData::Float(b)) => a == b,
+            (MemData::BigInt(a), MemData::BigInt(b)) => a == b,
+            (MemData::RecordType(a), MemData::RecordType(b)) => a == b,
+            _ => false,
+        }
+    }
+}
@@ -1,9 +1,12 @@
 use std::fmt;
+use std::io;
 use super::{
     Type,
+    Symbol,
     IdentID,
     ConstID,
     Op,
+    VerifyError,
 };
 
 #[derive(Debug)]
@@ -32,8 +35,10 @@ impl ::std::error::Error for RuntimeError {
 
 #[derive(Debug)]
 pub enum Error {
-    TypeError(Type, Type),
-    VariableNotFound(usize, IdentID),
+    /// Expected, found, and the names of both record types when both are records
+    TypeError(Type, Type, Option<(Symbol, Symbol)>),
+    /// Scope, ident and the var string bound to it, if any
+    VariableNotFound(usize, IdentID, Option<Symbol>),
     ConstantNotFound(ConstID),
     IllegalStackPop,
     IllegalRegisterPop,
@@ -41,14 +46,40 @@ pub enum Error {
     RuntimeErrorInSubJob(Box<RuntimeError>),
     BadOperandTypes(&'static str, Type, Type),
     BadScopeIndex(usize),
+    SyntaxError(usize, usize, &'static str),
+    UnexpectedEof(usize, usize),
+    CompileError(&'static str, String),
+    Io(io::Error),
+    BadBin(&'static str),
+    UnsupportedBinVersion(u16),
+    Unserializable(Type),
+    EncodingOverflow(&'static str, u32),
+    BadEncoding(&'static str),
+    VerificationFailed(Vec<VerifyError>),
+    DivisionByZero,
+    IndexOutOfRange(i64, usize),
+    /// Start, end
+    BadRange(usize, usize),
+    /// Expected, found
+    WrongArgumentCount(usize, usize),
+    UnhashableKey(Type),
+    ParseError(Type, String),
+    IdentsExhausted,
+    ConstantsExhausted,
+    /// How deep procedures may nest
+    RecursionLimit(usize),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
-            Error::TypeError(ref a, ref b) =>
+            Error::TypeError(_, _, Some((ref a, ref b))) =>
+                write!(f, "expected a record of type `{}` but found one of type `{}`", a, b),
+            Error::TypeError(ref a, ref b, None) =>
                 write!(f, "expected type `{:?}` but found `{:?}`", a, b),
-            Error::VariableNotFound(ref scope, ref id) =>
+            Error::VariableNotFound(ref scope, _, Some(ref name)) =>
+                write!(f, "variable not found in scope `{}`: `{}`", scope, name),
+            Error::VariableNotFound(ref scope, ref id, None) =>
                 write!(f, "variable not found in scope `{}`: {:?}", scope, id),
             Error::ConstantNotFound(ref id) =>
                 write!(f, "constant not found: {:?}", id),
@@ -64,6 +95,49 @@ impl fmt::Display for Error {
                 write!(f, "bad operand types: attemped `{}` on types `{:?}` and `{:?}`", o, a, b),
             Error::BadScopeIndex(ref i) =>
                 write!(f, "bad scope index: {}", i),
+            Error::SyntaxError(ref l, ref c, ref m) =>
+                write!(f, "syntax error at line {}, column {}: {}", l, c, m),
+            Error::UnexpectedEof(ref l, ref c) =>
+                write!(f, "unexpected end of input at line {}, column {}", l, c),
+            Error::CompileError(ref m, ref form) =>
+                write!(f, "compile error: {}: `{}`", m, form),
+            Error::Io(ref e) =>
+                write!(f, "i/o error: {}", e),
+            Error::BadBin(ref m) =>
+                write!(f, "malformed bytecode file: {}", m),
+            Error::UnsupportedBinVersion(ref v) =>
+                write!(f, "unsupported bytecode file version: {}", v),
+            Error::Unserializable(ref t) =>
+                write!(f, "values of type `{:?}` cannot be serialized", t),
+            Error::EncodingOverflow(ref field, ref v) =>
+                write!(f, "value `{}` does not fit the encoded {} field", v, field),
+            Error::BadEncoding(ref m) =>
+                write!(f, "malformed operation encoding: {}", m),
+            Error::DivisionByZero =>
+                write!(f, "division by zero"),
+            Error::IndexOutOfRange(ref i, ref len) =>
+                write!(f, "index {} is out of range for length {}", i, len),
+            Error::BadRange(ref start, ref end) =>
+                write!(f, "range start {} is past its end {}", start, end),
+            Error::WrongArgumentCount(ref expected, ref found) =>
+                write!(f, "expected {} arguments but found {}", expected, found),
+            Error::UnhashableKey(ref t) =>
+                write!(f, "values of type `{:?}` cannot be used as map keys", t),
+            Error::ParseError(ref t, ref s) =>
+                write!(f, "cannot parse {:?} as `{:?}`", s, t),
+            Error::IdentsExhausted =>
+                write!(f, "ran out of identifier ids"),
+            Error::ConstantsExhausted =>
+                write!(f, "ran out of constant ids"),
+            Error::RecursionLimit(ref n) =>
+                write!(f, "recursion limit exceeded: more than {} nested procedures", n),
+            Error::VerificationFailed(ref problems) => {
+                write!(f, "bytecode failed verification:")?;
+                for p in problems {
+                    write!(f, "\n    {}", p)?;
+                }
+                Ok(())
+            },
         }
     }
 }
@@ -80,6 +154,42 @@ impl ::std::error::Error for Error {
             Error::RuntimeErrorInSubJob(..) => "runtime error occured while running a subjob",
             Error::BadOperandTypes(..)   => "bad operand types",
             Error::BadScopeIndex(..)     => "bad scope index",
+            Error::SyntaxError(..)       => "syntax error",
+            Error::UnexpectedEof(..)     => "unexpected end of input",
+            Error::CompileError(..)      => "compile error",
+            Error::Io(..)                => "i/o error",
+            Error::BadBin(..)            => "malformed bytecode file",
+            Error::UnsupportedBinVersion(..) => "unsupported bytecode file version",
+            Error::Unserializable(..)    => "value cannot be serialized",
+            Error::EncodingOverflow(..)  => "value does not fit its encoded field",
+            Error::BadEncoding(..)       => "malformed operation encoding",
+            Error::VerificationFailed(..) => "bytecode failed verification",
+            Error::DivisionByZero        => "division by zero",
+            Error::IndexOutOfRange(..)   => "index out of range",
+            Error::BadRange(..)          => "range starts past its end",
+            Error::WrongArgumentCount(..) => "wrong number of arguments",
+            Error::UnhashableKey(..)     => "unhashable map key",
+            Error::ParseError(..)        => "cannot parse value",
+            Error::IdentsExhausted       => "ran out of identifier ids",
+            Error::ConstantsExhausted    => "ran out of constant ids",
+            Error::RecursionLimit(..)    => "recursion limit exceeded",
         }
     }
 }
+
+impl Error {
+    /// Wraps the error of a nested procedure. Hitting the recursion limit isn't
+    /// wrapped once per level, which would make it as deep as the recursion
+    pub fn subjob(e: RuntimeError) -> Error {
+        match e.error {
+            Error::RecursionLimit(..) => e.error,
+            _ => Error::RuntimeErrorInSubJob(Box::new(e)),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
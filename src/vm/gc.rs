@@ -0,0 +1,280 @@
+//! Collection of the reference cycles `Rc` alone never frees
+//!
+//! A lambda holds its environment, whose frames may hold the lambda back (any
+//! recursive `define` does), and pairs, vectors, maps and records can be
+//! mutated to contain themselves. A cycle can only be closed by mutating one
+//! of its objects, so the `Heap` remembers, weakly, every object a value is
+//! stored into: those are the candidates a collection starts from.
+//!
+//! A collection marks what the roots reach, then looks at the candidates left
+//! and what they reach. Of those, an object is garbage if every strong
+//! reference to it comes from another such object; one also held from outside
+//! (like a value returned by `VM::call` the embedder still has) is kept, along
+//! with what it reaches. Garbage is emptied, which breaks its cycles and lets
+//! `Rc` free it.
+
+use super::{
+    MemData,
+    Map,
+    Cons,
+    WeakCons,
+    Record,
+    WeakRecord,
+    EnvNode,
+    Binding,
+};
+
+use std::cell::RefCell;
+use std::collections::{
+    HashMap,
+    HashSet,
+};
+use std::rc::{
+    Rc,
+    Weak,
+};
+
+/// Candidates remembered before a collection is due, at least
+const MIN_LIMIT: usize = 4096;
+
+/// A shared allocation cycles can go through
+#[derive(Clone)]
+pub enum Node {
+    Env(Rc<RefCell<EnvNode>>),
+    Binding(Binding),
+    /// Never emptied itself: it is immutable, so any cycle through it also goes
+    /// through one of the others
+    Value(Rc<MemData>),
+    Pair(Cons),
+    Vector(Rc<RefCell<Vec<MemData>>>),
+    Map(Rc<RefCell<Map>>),
+    Record(Record),
+}
+
+enum WeakNode {
+    Env(Weak<RefCell<EnvNode>>),
+    Binding(Weak<RefCell<Rc<MemData>>>),
+    Pair(WeakCons),
+    Vector(Weak<RefCell<Vec<MemData>>>),
+    Map(Weak<RefCell<Map>>),
+    Record(WeakRecord),
+}
+
+#[derive(PartialEq, Eq, Debug, Default, Copy, Clone)]
+pub struct HeapStats {
+    /// Objects currently remembered as possible members of a cycle
+    pub tracked: usize,
+    /// Collections run so far
+    pub collections: usize,
+    /// Objects freed by the last collection
+    pub last_reclaimed: usize,
+    /// Objects freed by all collections
+    pub reclaimed: usize,
+}
+
+pub struct Heap {
+    candidates: HashMap<usize, WeakNode>,
+    /// Number of candidates at which dead ones are dropped and a collection is due
+    limit: usize,
+    due: bool,
+    stats: HeapStats,
+}
+
+impl Node {
+    fn addr(&self) -> usize {
+        match *self {
+            Node::Env(ref n) => Rc::as_ptr(n) as *const () as usize,
+            Node::Binding(ref b) => Rc::as_ptr(b) as *const () as usize,
+            Node::Value(ref v) => Rc::as_ptr(v) as *const () as usize,
+            Node::Pair(ref c) => c.as_ptr() as usize,
+            Node::Vector(ref v) => Rc::as_ptr(v) as *const () as usize,
+            Node::Map(ref m) => Rc::as_ptr(m) as *const () as usize,
+            Node::Record(ref r) => r.as_ptr() as usize,
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match *self {
+            Node::Env(ref n) => Rc::strong_count(n),
+            Node::Binding(ref b) => Rc::strong_count(b),
+            Node::Value(ref v) => Rc::strong_count(v),
+            Node::Pair(ref c) => c.strong_count(),
+            Node::Vector(ref v) => Rc::strong_count(v),
+            Node::Map(ref m) => Rc::strong_count(m),
+            Node::Record(ref r) => r.strong_count(),
+        }
+    }
+
+    fn downgrade(&self) -> Option<WeakNode> {
+        Some(match *self {
+            Node::Env(ref n) => WeakNode::Env(Rc::downgrade(n)),
+            Node::Binding(ref b) => WeakNode::Binding(Rc::downgrade(b)),
+            Node::Value(..) => return None,
+            Node::Pair(ref c) => WeakNode::Pair(c.downgrade()),
+            Node::Vector(ref v) => WeakNode::Vector(Rc::downgrade(v)),
+            Node::Map(ref m) => WeakNode::Map(Rc::downgrade(m)),
+            Node::Record(ref r) => WeakNode::Record(r.downgrade()),
+        })
+    }
+
+    /// The nodes `v` holds, one per strong reference
+    pub fn of_data(v: &MemData, out: &mut Vec<Node>) {
+        match *v {
+            MemData::Pointer(ref p) => out.push(Node::Value(Rc::clone(p))),
+            MemData::Lambda(_, ref env) => env.gc_nodes(out),
+            MemData::Pair(ref c) => out.push(Node::Pair(c.clone())),
+            MemData::Vector(ref v) => out.push(Node::Vector(Rc::clone(v))),
+            MemData::Map(ref m) => out.push(Node::Map(Rc::clone(m))),
+            MemData::Record(ref r) => out.push(Node::Record(r.clone())),
+            _ => (),
+        }
+    }
+
+    /// The nodes this one holds, one per strong reference
+    fn children(&self, out: &mut Vec<Node>) {
+        match *self {
+            Node::Env(ref n) => n.borrow().gc_children(out),
+            Node::Binding(ref b) => out.push(Node::Value(Rc::clone(&b.borrow()))),
+            Node::Value(ref v) => Node::of_data(v, out),
+            Node::Pair(ref c) => {
+                Node::of_data(&c.car(), out);
+                Node::of_data(&c.cdr(), out);
+            },
+            Node::Vector(ref v) => v.borrow().iter().for_each(|v| Node::of_data(v, out)),
+            Node::Map(ref m) => m.borrow().values().for_each(|v| Node::of_data(v, out)),
+            Node::Record(ref r) => r.slots().iter().for_each(|v| Node::of_data(v, out)),
+        }
+    }
+
+    /// Drops whatever the node holds
+    fn clear(&self) {
+        match *self {
+            Node::Env(ref n) => n.borrow_mut().gc_clear(),
+            Node::Binding(ref b) => *b.borrow_mut() = Rc::new(MemData::Nil),
+            Node::Value(..) => (),
+            Node::Pair(ref c) => {
+                c.set_car(MemData::Nil);
+                c.set_cdr(MemData::Nil);
+            },
+            Node::Vector(ref v) => v.borrow_mut().clear(),
+            Node::Map(ref m) => m.borrow_mut().clear(),
+            Node::Record(ref r) => r.clear(),
+        }
+    }
+}
+
+impl WeakNode {
+    fn upgrade(&self) -> Option<Node> {
+        match *self {
+            WeakNode::Env(ref n) => n.upgrade().map(Node::Env),
+            WeakNode::Binding(ref b) => b.upgrade().map(Node::Binding),
+            WeakNode::Pair(ref c) => c.upgrade().map(Node::Pair),
+            WeakNode::Vector(ref v) => v.upgrade().map(Node::Vector),
+            WeakNode::Map(ref m) => m.upgrade().map(Node::Map),
+            WeakNode::Record(ref r) => r.upgrade().map(Node::Record),
+        }
+    }
+
+    fn is_alive(&self) -> bool {
+        match *self {
+            WeakNode::Env(ref n) => n.strong_count() > 0,
+            WeakNode::Binding(ref b) => b.strong_count() > 0,
+            WeakNode::Vector(ref v) => v.strong_count() > 0,
+            WeakNode::Map(ref m) => m.strong_count() > 0,
+            WeakNode::Pair(..) | WeakNode::Record(..) => self.upgrade().is_some(),
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self {
+            candidates: HashMap::new(),
+            limit: MIN_LIMIT,
+            due: false,
+            stats: HeapStats::default(),
+        }
+    }
+
+    /// Remembers that a value was stored into `node`
+    pub fn track(&mut self, node: &Node) {
+        if let Some(w) = node.downgrade() {
+            self.candidates.insert(node.addr(), w);
+        }
+        if self.candidates.len() >= self.limit {
+            // Mostly frames of calls returned long ago
+            self.candidates.retain(|_, w| w.is_alive());
+            self.limit = ::std::cmp::max(MIN_LIMIT, 2 * self.candidates.len());
+            self.due = true;
+        }
+    }
+
+    /// Whether enough candidates were tracked since the last collection
+    pub fn collection_due(&self) -> bool {
+        self.due
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats { tracked: self.candidates.len(), ..self.stats }
+    }
+
+    /// Frees the cycles neither `roots` nor anything outside the heap reach
+    pub fn collect(&mut self, roots: Vec<Node>) -> HeapStats {
+        // Mark
+        let mut live = HashSet::new();
+        let mut stack = roots;
+        while let Some(n) = stack.pop() {
+            if live.insert(n.addr()) {
+                n.children(&mut stack);
+            }
+        }
+
+        // Everything the unmarked candidates reach, each held exactly once here
+        let mut unmarked: HashMap<usize, Node> = HashMap::new();
+        stack = self.candidates.values().filter_map(WeakNode::upgrade).collect();
+        while let Some(n) = stack.pop() {
+            let addr = n.addr();
+            if !live.contains(&addr) && !unmarked.contains_key(&addr) {
+                n.children(&mut stack);
+                unmarked.insert(addr, n);
+            }
+        }
+
+        let mut internal: HashMap<usize, usize> = HashMap::new();
+        for n in unmarked.values() {
+            let mut children = Vec::new();
+            n.children(&mut children);
+            for c in children {
+                if unmarked.contains_key(&c.addr()) {
+                    *internal.entry(c.addr()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        // Keep what is referenced from outside, and what that reaches
+        stack = unmarked.iter()
+            .filter(|&(addr, n)| n.strong_count() > 1 + internal.get(addr).cloned().unwrap_or(0))
+            .map(|(_, n)| n.clone())
+            .collect();
+        while let Some(n) = stack.pop() {
+            if unmarked.remove(&n.addr()).is_some() {
+                n.children(&mut stack);
+            }
+        }
+
+        // Sweep
+        let garbage: Vec<Node> = unmarked.into_values().collect();
+        garbage.iter().for_each(Node::clear);
+        let freed: Vec<WeakNode> = garbage.iter().filter_map(Node::downgrade).collect();
+        drop(garbage);
+        let reclaimed = freed.iter().filter(|w| !w.is_alive()).count();
+
+        self.candidates.retain(|_, w| w.is_alive());
+        self.limit = ::std::cmp::max(MIN_LIMIT, 2 * self.candidates.len());
+        self.due = false;
+        self.stats.collections += 1;
+        self.stats.last_reclaimed = reclaimed;
+        self.stats.reclaimed += reclaimed;
+        self.stats()
+    }
+}
@@ -0,0 +1,101 @@
+//! Hash maps, and the operations on them shared by the VM and embedders
+
+use super::{
+    MemData,
+    BigInt,
+    Symbol,
+    Type,
+    Error,
+};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// The values that can key a map
+#[derive(PartialEq, Eq, Hash, Debug, Clone)]
+pub enum MapKey {
+    Int(i64),
+    BigInt(BigInt),
+    Str(String),
+    Char(char),
+    Bool(bool),
+    Symbol(Symbol),
+    Nil,
+}
+
+impl MapKey {
+    pub fn new(v: &MemData) -> Result<MapKey, Error> {
+        Ok(match *v.deref() {
+            MemData::Int(i)        => MapKey::Int(i),
+            MemData::BigInt(ref b) => MapKey::BigInt(b.clone()),
+            MemData::Str(ref s)    => MapKey::Str(s.clone()),
+            MemData::Char(c)       => MapKey::Char(c),
+            MemData::Bool(b)       => MapKey::Bool(b),
+            MemData::Symbol(s)     => MapKey::Symbol(s),
+            MemData::Nil           => MapKey::Nil,
+            ref v => return Err(Error::UnhashableKey(v.get_type())),
+        })
+    }
+
+    pub fn to_data(&self) -> MemData {
+        match *self {
+            MapKey::Int(i)        => MemData::Int(i),
+            MapKey::BigInt(ref b) => MemData::BigInt(b.clone()),
+            MapKey::Str(ref s)    => MemData::Str(s.clone()),
+            MapKey::Char(c)       => MemData::Char(c),
+            MapKey::Bool(b)       => MemData::Bool(b),
+            MapKey::Symbol(s)     => MemData::Symbol(s),
+            MapKey::Nil           => MemData::Nil,
+        }
+    }
+}
+
+pub type Map = HashMap<MapKey, MemData>;
+
+impl MemData {
+    /// A new map holding the given key/value pairs
+    pub fn map<I>(pairs: I) -> Result<MemData, Error>
+        where I: IntoIterator<Item = (MemData, MemData)>
+    {
+        let mut m = Map::new();
+        for (k, v) in pairs {
+            m.insert(MapKey::new(&k)?, v);
+        }
+        Ok(MemData::Map(Rc::new(RefCell::new(m))))
+    }
+
+    fn as_map(&self) -> Result<&Rc<RefCell<Map>>, Error> {
+        match *self.deref() {
+            MemData::Map(ref m) => Ok(m),
+            ref v => Err(v.wrong_type(Type::Map)),
+        }
+    }
+
+    /// Looks `key` up, `None` if the map doesn't hold it
+    pub fn map_get(&self, key: &MemData) -> Result<Option<MemData>, Error> {
+        let key = MapKey::new(key)?;
+        Ok(self.as_map()?.borrow().get(&key).cloned())
+    }
+
+    /// Binds `key` to `val`, returning the value it replaced
+    pub fn map_insert(&self, key: &MemData, val: MemData) -> Result<Option<MemData>, Error> {
+        let key = MapKey::new(key)?;
+        Ok(self.as_map()?.borrow_mut().insert(key, val))
+    }
+
+    /// Unbinds `key`, returning the value it was bound to
+    pub fn map_remove(&self, key: &MemData) -> Result<Option<MemData>, Error> {
+        let key = MapKey::new(key)?;
+        Ok(self.as_map()?.borrow_mut().remove(&key))
+    }
+
+    /// The keys of the map, in no particular order
+    pub fn map_keys(&self) -> Result<Vec<MemData>, Error> {
+        Ok(self.as_map()?.borrow().keys().map(MapKey::to_data).collect())
+    }
+
+    pub fn map_len(&self) -> Result<usize, Error> {
+        Ok(self.as_map()?.borrow().len())
+    }
+}
@@ -12,6 +12,9 @@ use std::rc::{
 use super::{
     // ConstData,
     MemData,
+    Symbol,
+    Heap,
+    Node,
     // Instructions,
     IdentID,
     ConstID,
@@ -20,6 +23,8 @@ use super::{
 
 pub type Constants = Vec<Rc<MemData>>;
 pub type VarStrings = HashMap<String, IdentID>;
+/// The current value of a variable, shared by the frames that captured it
+pub type Binding = Rc<RefCell<Rc<MemData>>>;
 
 #[derive(Clone)]
 pub struct Environment {
@@ -29,17 +34,18 @@ pub struct Environment {
     len: usize,
 
     consts:      Rc<RefCell<Constants>>,
-    var_strings: Rc<RefCell<VarStrings>>
+    var_strings: Rc<RefCell<VarStrings>>,
+    heap:        Rc<RefCell<Heap>>,
 }
 
-struct EnvNode {
+pub struct EnvNode {
     parent: Option<Rc<RefCell<EnvNode>>>,
     child:  Option<Weak<RefCell<EnvNode>>>,
     frame:  Frame,
 }
 
 pub struct Frame {
-    vars: HashMap<IdentID, Rc<MemData>>,
+    vars: HashMap<IdentID, Binding>,
 }
 
 // pub struct Memory {
@@ -62,6 +68,7 @@ impl Environment {
 
             consts,
             var_strings: Rc::new(RefCell::new(HashMap::new())),
+            heap: Rc::new(RefCell::new(Heap::new())),
         }
     }
 
@@ -75,7 +82,9 @@ impl Environment {
     }
 
     pub fn define(&mut self, ident: IdentID, val: MemData) -> Result<(), Error> {
-        Ok(self.env_tail.borrow_mut().define(ident, val))
+        self.env_tail.borrow_mut().define(ident, val);
+        self.heap.borrow_mut().track(&Node::Env(Rc::clone(&self.env_tail)));
+        Ok(())
     }
 
     fn define_n(&mut self, frame: usize, ident: IdentID, val: MemData) -> Result<(), Error> {
@@ -87,10 +96,45 @@ impl Environment {
     }
 
     pub fn get(&self, ident: &IdentID) -> Result<MemData, Error> {
-        self.env_tail.borrow().get(ident)
+        self.env_tail.borrow().get(ident).map_err(|e| self.named(e))
         // Ref::map(self.env_tail.borrow(), |t| t.get(ident))
     }
 
+    /// Replaces the value of `ident` in the nearest frame binding it
+    pub fn set(&mut self, ident: IdentID, val: MemData) -> Result<(), Error> {
+        let binding = self.env_tail.borrow().binding(&ident).map_err(|e| self.named(e))?;
+        *binding.borrow_mut() = Rc::new(val);
+        self.heap.borrow_mut().track(&Node::Binding(binding));
+        Ok(())
+    }
+
+    /// Binds `ident` in the last frame to the very variable `from` sees, so
+    /// that both environments observe `set`s made through the other
+    pub fn capture(&mut self, ident: IdentID, from: &Environment) -> Result<(), Error> {
+        let binding = from.env_tail.borrow().binding(&ident).map_err(|e| from.named(e))?;
+        self.env_tail.borrow_mut().frame.bind(ident, binding);
+        self.heap.borrow_mut().track(&Node::Env(Rc::clone(&self.env_tail)));
+        Ok(())
+    }
+
+    /// Remembers that a value was stored into `v`, see `gc`
+    pub fn track(&self, v: &MemData) {
+        let mut nodes = Vec::new();
+        Node::of_data(v.deref(), &mut nodes);
+        let mut heap = self.heap.borrow_mut();
+        nodes.iter().for_each(|n| heap.track(n));
+    }
+
+    pub fn heap(&self) -> Rc<RefCell<Heap>> {
+        Rc::clone(&self.heap)
+    }
+
+    /// The frames this environment holds, as collector nodes
+    pub fn gc_nodes(&self, out: &mut Vec<Node>) {
+        out.push(Node::Env(Rc::clone(&self.env_head)));
+        out.push(Node::Env(Rc::clone(&self.env_tail)));
+    }
+
     // pub fn get_node_mut(&self, i: usize) -> Result<Rc<RefCell<EnvNode>>, Error> {
     //     if i >= self.len {
     //         Err(Error::BadScopeIndex(i))
@@ -108,10 +152,43 @@ impl Environment {
         self.env_tail.borrow_mut().set_parent(old_tail);
     }
 
+    /// Number of frames, the global one included
+    #[cfg(test)]
+    pub fn depth(&self) -> usize {
+        self.len
+    }
+
+    /// The global scope alone, without the frames pushed on top of it
+    pub fn root(&self) -> Environment {
+        Self {
+            env_head: Rc::clone(&self.env_head),
+            env_tail: Rc::clone(&self.env_head),
+            len: 1,
+
+            consts: Rc::clone(&self.consts),
+            var_strings: Rc::clone(&self.var_strings),
+            heap: Rc::clone(&self.heap),
+        }
+    }
+
+    /// Makes sure the last frame belongs to this environment alone, stacking a
+    /// fresh one if it is shared. Unlike `new_frame` the parent doesn't learn
+    /// about its new child, so the environments sharing it are left untouched.
+    pub fn private_frame(&mut self) {
+        if Rc::strong_count(&self.env_tail) > 1 {
+            let node = Rc::new(RefCell::new(EnvNode::new()));
+            node.borrow_mut().set_parent(Rc::clone(&self.env_tail));
+            self.env_tail = node;
+            self.len += 1;
+        }
+    }
+
     pub fn pop_frame(&mut self) -> Result<(), Error> {
+        // The popped node keeps its parent: closures created in it still need their
+        // enclosing scopes
+        let new_tail = self.env_tail.borrow().get_parent().cloned().ok_or(Error::IllegalStackPop)?;
         self.len -= 1;
 
-        let new_tail = self.env_tail.borrow_mut().pop_parent().ok_or(Error::IllegalStackPop)?;
         let _ = new_tail.borrow_mut().pop_child();
         self.env_tail = new_tail;
         Ok(())
@@ -154,36 +231,59 @@ impl Environment {
                          |r| Ok(MemData::Pointer(Rc::clone(r))))
     }
 
-    pub fn new_ident_id(&mut self, var_str: Option<String>) -> IdentID {
-        let id = self.max_id().unwrap_or(0) + 1;
+    pub fn new_ident_id(&mut self, var_str: Option<String>) -> Result<IdentID, Error> {
+        // Ids bound to a var string may only be defined in frames that are gone by now
+        let bound = self.var_strings.borrow().values().max().copied();
+        let id = match ::std::cmp::max(self.max_id(), bound) {
+            Some(max) => max.checked_add(1).ok_or(Error::IdentsExhausted)?,
+            None => 1,
+        };
         if let Some(s) = var_str {
             self.bind_var_string(s, id);
         }
-        id
+        Ok(id)
+    }
+
+    /// Removes `ident` from the global scope
+    pub fn undefine(&mut self, ident: &IdentID) -> Result<(), Error> {
+        self.env_head.borrow_mut().frame.vars.remove(ident)
+            .map(|_| ())
+            .ok_or_else(|| self.named(Error::VariableNotFound(0, *ident, None)))
     }
 
     pub fn bind_var_string(&mut self, s: String, id: IdentID) {
         let _ = self.var_strings.borrow_mut().insert(s,  id);
     }
 
+    pub fn var_strings(&self) -> Vec<(String, IdentID)> {
+        self.var_strings.borrow().iter().map(|(s, id)| (s.clone(), *id)).collect()
+    }
+
     pub fn get_ident(&self, s: &String) -> Option<IdentID> {
         self.var_strings.borrow().get(s).map(|id| *id)
     }
 
+    /// Names the variable a `VariableNotFound` is about, for the error message
+    fn named(&self, e: Error) -> Error {
+        match e {
+            Error::VariableNotFound(scope, id, None) => {
+                let name = self.ident_name(id).map(|s| Symbol::intern(&s));
+                Error::VariableNotFound(scope, id, name)
+            },
+            e => e,
+        }
+    }
+
+    /// The var string bound to `id`, if any
+    pub fn ident_name(&self, id: IdentID) -> Option<String> {
+        self.var_strings.borrow().iter().find(|&(_, i)| *i == id).map(|(s, _)| s.clone())
+    }
+
     pub fn load_const(&mut self, val: MemData) -> usize {
         let mut c = self.consts.borrow_mut();
         c.push(Rc::new(val));
         c.len() - 1
     }
-
-    pub fn max_const_id(&self) -> Option<usize> {
-        let len = self.consts.borrow().len();
-        if len == 0 {
-            None
-        } else {
-            Some(len - 1)
-        }
-    }
 }
 
 impl ::std::fmt::Debug for Environment {
@@ -254,6 +354,16 @@ impl EnvNode {
         }
     }
 
+    fn binding(&self, ident: &IdentID) -> Result<Binding, Error> {
+        match self.frame.binding(ident) {
+            Some(b) => Ok(b),
+            None => match self.parent {
+                Some(ref p) => p.borrow().binding(ident),
+                None => Err(Error::VariableNotFound(0, *ident, None)),
+            },
+        }
+    }
+
     fn get(&self, ident: &IdentID) -> Result<MemData, Error> {
         let val = self.frame.get(ident);
         if val.is_some() {
@@ -263,7 +373,7 @@ impl EnvNode {
                 self.parent.as_ref().unwrap().borrow().get(ident)
             } else {
                 // FIXME: scope for error set to const 0
-                Err(Error::VariableNotFound(0, *ident))
+                Err(Error::VariableNotFound(0, *ident, None))
             }
         }
     }
@@ -310,14 +420,23 @@ impl EnvNode {
     //     self.child.as_ref().map(|ref c| Rc::clone(c))
     // }
 
-    fn pop_parent(&mut self) -> Option<Rc<RefCell<EnvNode>>> {
-        ::std::mem::replace(&mut self.parent, None)
-    }
-
     fn pop_child(&mut self) -> Option<Rc<RefCell<EnvNode>>> {
         ::std::mem::replace(&mut self.child, None).map(|c| c.upgrade().unwrap())
     }
 
+    /// The parent and the variables of the frame, as collector nodes
+    pub fn gc_children(&self, out: &mut Vec<Node>) {
+        if let Some(ref p) = self.parent {
+            out.push(Node::Env(Rc::clone(p)));
+        }
+        out.extend(self.frame.vars.values().map(|b| Node::Binding(Rc::clone(b))));
+    }
+
+    /// Forgets the variables of the frame
+    pub fn gc_clear(&mut self) {
+        self.frame.vars.clear();
+    }
+
     fn get_frame(&self) -> &Frame {
         &self.frame
     }
@@ -335,11 +454,19 @@ impl Frame {
     }
 
     pub fn define(&mut self, id: IdentID, val: MemData) {
-        self.vars.insert(id, Rc::new(val));
+        self.vars.insert(id, Rc::new(RefCell::new(Rc::new(val))));
+    }
+
+    fn bind(&mut self, id: IdentID, binding: Binding) {
+        self.vars.insert(id, binding);
+    }
+
+    fn binding(&self, id: &IdentID) -> Option<Binding> {
+        self.vars.get(id).cloned()
     }
 
     pub fn get(&self, id: &IdentID) -> Option<MemData> {
-        self.vars.get(id).map(|rc| MemData::Pointer(Rc::clone(rc)))
+        self.vars.get(id).map(|b| MemData::Pointer(Rc::clone(&b.borrow())))
     }
 
     pub fn max_id(&self) -> Option<&IdentID> {
@@ -1,15 +1,45 @@
-use std::collections::LinkedList;
+use std::collections::{
+    LinkedList,
+    HashMap,
+};
 
+// Only the tests write programs by hand since the demo in main gave way to the REPL
 #[macro_use]
+#[allow(unused_macros)]
 pub mod macros;
 
 mod mem;
 mod data;
+mod bigint;
+mod symbol;
+mod map;
+mod pair;
+mod strings;
+mod chars;
+mod printer;
+mod equal;
+mod record;
+mod gc;
 mod err;
+mod binfmt;
+pub mod encode;
+mod asm;
+mod verify;
 
 use self::mem::*;
 pub use self::data::*;
+pub use self::bigint::*;
+pub use self::symbol::*;
+pub use self::map::*;
+pub use self::pair::*;
+pub use self::strings::*;
+pub use self::chars::*;
+pub use self::printer::*;
+pub use self::record::*;
+pub use self::gc::*;
 pub use self::err::*;
+pub use self::asm::*;
+pub use self::verify::*;
 
 use std::cell::{RefCell};
 use std::rc::Rc;
@@ -21,6 +51,12 @@ use std::rc::Rc;
 // }
 
 
+/// How deeply a job may nest procedures (calls, the branches of IFE, CAN, ...)
+/// before giving up with `Error::RecursionLimit`
+pub const MAX_DEPTH: usize = 2000;
+/// Stack a thread running a VM needs to get to `MAX_DEPTH`, debug builds included
+pub const STACK_SIZE: usize = 256 << 20;
+
 bitflags! {
     pub struct LoadOpts: u8 {
         const OVERRIDE_VAR_STRINGS = 0b00000001;
@@ -36,6 +72,8 @@ pub struct Job {
     // FIXME?: should the self.reg_stack be a Vec<&MemData> instead?
     reg_stack: LinkedList<MemData>,
     recording: usize,
+    /// Number of procedures being executed, see `MAX_DEPTH`
+    depth: usize,
 }
 
 pub struct VM {
@@ -43,6 +81,8 @@ pub struct VM {
     // registers: Registers,
     // memory:  Rc<RefCell<Memory>>,
     consts: Rc<RefCell<Constants>>,
+    /// Where the hashable constants went, see `intern_const`
+    const_index: HashMap<MapKey, ConstID>,
     memory: Environment,
     jobs: Vec<Job>,
 }
@@ -55,6 +95,7 @@ impl Job {
             // scope: 0,
             reg_stack: LinkedList::new(),
             recording: 0,
+            depth: 0,
         }
     }
 
@@ -95,6 +136,15 @@ impl Job {
         })
     }
 
+    /// Pops the last `n` values of the register, in the order they were pushed
+    fn pop_n(&mut self, n: usize) -> Result<LinkedList<MemData>, Error> {
+        let len = self.reg_stack.len();
+        if n > len {
+            return Err(Error::IllegalRegisterPop);
+        }
+        Ok(self.reg_stack.split_off(len - n))
+    }
+
     pub fn run_instruction(&mut self, inst: &Op) -> Result<(), Error> {
         if self.recording > 0 {
             self.recording -= 1;
@@ -117,28 +167,65 @@ impl Job {
                 self.recording = inst.n.unwrap_or(1) as usize;
             },
 
-            OpCode::LMB | OpCode::PRC => {
+            OpCode::LMB | OpCode::PRC | OpCode::DFN => {
                 let n = inst.n.expect("getting quatifier");
                 let mut is: Vec<Op> = Vec::with_capacity(n as usize);
 
-                let n = self.reg_stack.len() - n as usize;
-                for v in self.reg_stack.split_off(n).into_iter() {
+                for v in self.pop_n(n as usize)?.into_iter() {
                     map_as!(v => Inst(i) => is.push(i))?;
                 }
 
-                self.reg_stack.push_back(
-                    match inst.opcode {
-                        OpCode::LMB => MemData::Lambda(is.into(), self.env.clone()),
-                        OpCode::PRC | _ => MemData::Proc(is.into()),
-                    })
+                let v = match inst.opcode {
+                    OpCode::PRC => MemData::Proc(is.into()),
+                    _ => MemData::Lambda(is.into(), self.env.clone()),
+                };
+                if let OpCode::DFN = inst.opcode {
+                    let ident = inst.ident.expect("getting identifier");
+                    let v = self.name_after(ident, v);
+                    self.env.define(ident, v)?;
+                    if ! inst.mute {
+                        self.reg_stack.push_back(self.env.get(&ident)?);
+                    }
+                } else {
+                    self.reg_stack.push_back(v)
+                }
+            },
+            OpCode::CAP => {
+                // Closes over the named variable only, on top of the global scope
+                let v = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
+                // Taking the lambda of the previous CAP by value lets it keep its
+                // private frame for this capture too, instead of stacking another
+                let (p, mut env) = match v {
+                    MemData::Lambda(p, e) => (p, e),
+                    v => match *v.deref() {
+                        MemData::Proc(ref p) => (p.clone(), self.env.root()),
+                        MemData::Lambda(ref p, ref e) => (p.clone(), e.clone()),
+                        ref v => return Err(v.wrong_type(Type::Lambda)),
+                    },
+                };
+
+                env.private_frame();
+                if let Some(ident) = inst.ident {
+                    env.capture(ident, &self.env)?;
+                }
+                self.reg_stack.push_back(MemData::Lambda(p, env))
             },
-            OpCode::DVR => {
+            OpCode::RRR => {
+                self.pop_n(inst.n.unwrap_or(1) as usize)?;
+            },
+            OpCode::DVR | OpCode::SET => {
                 let val = if let Some(val) = inst.val {
                     self.env.get_const(&val)?.clone()
                 } else {
                     self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?
                 };
-                self.env.define(inst.ident.expect("getting identifier"), val)?; 
+                let ident = inst.ident.expect("getting identifier");
+                if inst.opcode == OpCode::SET {
+                    self.env.set(ident, val)?;
+                } else {
+                    let val = self.name_after(ident, val);
+                    self.env.define(ident, val)?;
+                }
 
                 if ! inst.mute {
                     self.reg_stack.push_back(self.env.get(&inst.ident.unwrap()).unwrap());
@@ -167,16 +254,16 @@ impl Job {
 
                 if !cond.is_false() {
                     self.execute(&tru, None)
-                        .map_err(|e| Error::RuntimeErrorInSubJob(Box::new(e)))?
+                        .map_err(Error::subjob)?
                 } else if let OpCode::IFE = inst.opcode {
                     self.execute(&fals.unwrap(), None)
-                        .map_err(|e| Error::RuntimeErrorInSubJob(Box::new(e)))?
+                        .map_err(Error::subjob)?
                 }
             },
             OpCode::CGT | OpCode::CLT | OpCode::CEQ => {
                 // Cond ordering
-                let n = self.reg_stack.len() - inst.n.expect("getting quantifier") as usize;
-                let mut iter = self.reg_stack.split_off(n)
+                let n = inst.n.expect("getting quantifier") as usize;
+                let mut iter = self.pop_n(n)?
                     .into_iter()
                     .peekable();
 
@@ -186,7 +273,7 @@ impl Job {
                         match inst.opcode {
                             OpCode::CGT     => { (v.gt(n))?  },
                             OpCode::CLT     => { (v.lt(n))?  },
-                            OpCode::CEQ | _ => { (v == n)  },
+                            OpCode::CEQ | _ => { (v.eq(n))? },
                         }
                     } else { true };
                 }
@@ -194,20 +281,47 @@ impl Job {
             },
             OpCode::CNT => {
                 // Cond NOT
+                let vals = if let Some(i) = inst.ident {
+                    let mut r = LinkedList::new();
+                    r.push_back(self.env.get(&i)?);
+                    r
+                } else {
+                    self.pop_n(inst.n.unwrap_or(1) as usize)?
+                };
+                for v in vals {
+                    self.reg_stack.push_back(MemData::Bool(v.is_false()))
+                }
+            },
+            OpCode::CAN | OpCode::COR => {
+                // Cond AND | Cond OR, short-circuiting over recorded procs
+                let n = inst.n.expect("getting quantifier") as usize;
+                let mut procs = Vec::with_capacity(n);
+                for v in self.pop_n(n)? {
+                    procs.push(map_as!(*v.deref() => Proc(ref p) => p.clone())?);
+                }
+
+                let mut r = MemData::Bool(inst.opcode == OpCode::CAN);
+                for p in procs {
+                    self.execute(&p, None)
+                        .map_err(Error::subjob)?;
+                    r = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
+                    if r.is_false() == (inst.opcode == OpCode::CAN) {
+                        break;
+                    }
+                }
+                self.reg_stack.push_back(r)
             },
             OpCode::CLL => {
                 let r = if let Some(i) = inst.ident {
                     self.call(&i)
-                        .map_err(|e| Error::RuntimeErrorInSubJob(Box::new(e)))?
+                        .map_err(Error::subjob)?
                 } else {
                     // setup
-                    let len = self.reg_stack.len();
                     let n = inst.n.expect("getting quantifier");
                     let mut insts = Vec::with_capacity(n as usize);
 
                     // prepare instrucitons list
-                    let n = len - n as usize;
-                    for v in self.reg_stack.split_off(n).into_iter() {
+                    for v in self.pop_n(n as usize)?.into_iter() {
                         map_as!(v => Inst(o) => insts.push(o) )?;
                     }
                     let insts = insts.into();
@@ -217,7 +331,7 @@ impl Job {
                     trace!("Entering subjob!");
                     self.execute(&insts, None)
                         .map_err(|e| { trace!("RUNTIME ERROR!"); e } )
-                        .map_err(|e| Error::RuntimeErrorInSubJob(Box::new(e)))?;
+                        .map_err(Error::subjob)?;
                     let r = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
                     trace!("Done subjob!");
                     self.env.pop_frame().unwrap();
@@ -233,8 +347,7 @@ impl Job {
                     ll.push_back(self.env.get(&ident)?.clone());
                     ll
                 } else {
-                    let n = self.reg_stack.len() - inst.n.unwrap() as usize;
-                    self.reg_stack.split_off(n)
+                    self.pop_n(inst.n.unwrap() as usize)?
                 }.into_iter().map(|v| v.convert(&inst.typ.unwrap()));
 
                 for v in vals {
@@ -242,41 +355,55 @@ impl Job {
                 }
             },
             OpCode::CAT => {
-                let n = self.reg_stack.len() - inst.n.unwrap() as usize;
-                let vals = self.reg_stack.split_off(n);
-                let mut val = "".to_owned();
+                let vals = self.pop_n(inst.n.unwrap() as usize)?;
+                let mut val = String::new();
                 for v in vals {
-                    map_as!(*v.deref() => Str(ref s) => {
-                        val = format!("{}{}", val, s)
-                    })?;
+                    map_as!(*v.deref() => Str(ref s) => val.push_str(s))?;
                 }
                 self.reg_stack.push_back(MemData::Str(val))
             },
+            OpCode::STR => {
+                let op = inst.n.and_then(StrOp::from_quantif).expect("getting string operation");
+                let args: Vec<MemData> = self.pop_n(op.arity())?.into_iter().collect();
+                self.reg_stack.push_back(op.run(&args)?)
+            },
+            OpCode::CEI | OpCode::CEV | OpCode::CES => {
+                let mut pair = self.pop_n(2)?.into_iter();
+                let (a, b) = (pair.next().unwrap(), pair.next().unwrap());
+                self.reg_stack.push_back(MemData::Bool(match inst.opcode {
+                    OpCode::CEI => a.is_eq(&b),
+                    OpCode::CEV => a.is_eqv(&b),
+                    _           => a.is_equal(&b),
+                }))
+            },
+            OpCode::CHR => {
+                let op = inst.n.and_then(CharOp::from_quantif).expect("getting char operation");
+                let v = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
+                self.reg_stack.push_back(op.run(&v)?)
+            },
             OpCode::CNS => {
-                let n = self.reg_stack.len() - 2;
-                let mut pair = self.reg_stack.split_off(n).into_iter();
-                let car = Box::new(pair.next().unwrap());
-                let cdr = Box::new(pair.next().unwrap());
-                self.reg_stack.push_back(MemData::Pair { car, cdr })
+                let mut pair = self.pop_n(2)?.into_iter();
+                let car = pair.next().unwrap();
+                let cdr = pair.next().unwrap();
+                self.reg_stack.push_back(MemData::cons(car, cdr))
             },
             OpCode::CAR | OpCode::CDR => {
                 let vals = if let Some(i) = inst.ident {
                     let mut r = LinkedList::new();
                     // let m = mem.borrow();
-                    r.push_back(self.env.get(&i)?.deref().clone());
+                    r.push_back(self.env.get(&i)?);
                     r
                 } else {
-                    let n = self.reg_stack.len() - inst.n.unwrap_or(1) as usize;
-                    self.reg_stack.split_off(n)
+                    self.pop_n(inst.n.unwrap_or(1) as usize)?
                 };
 
                 let mut r = LinkedList::new();
                 for v in vals.into_iter() {
-                    map_as!(v => Pair { car, cdr } => 
+                    map_as!(*v.deref() => Pair(ref c) =>
                             r.push_back(
-                                *match inst.opcode {
-                                    OpCode::CAR     => car,
-                                    OpCode::CDR | _ => cdr,
+                                match inst.opcode {
+                                    OpCode::CAR     => c.car(),
+                                    OpCode::CDR | _ => c.cdr(),
                                 }))?;
                 }
 
@@ -292,8 +419,7 @@ impl Job {
                     r.push_back(self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?);
                     r
                 } else {
-                    let n = self.reg_stack.len() - inst.n.unwrap_or(1) as usize;
-                    self.reg_stack.split_off(n)
+                    self.pop_n(inst.n.unwrap_or(1) as usize)?
                 };
                 self.reg_stack.push_back(
                     if vals.len() == 0 {
@@ -302,17 +428,25 @@ impl Job {
                         let mut vals = vals.into_iter();
                         let r = vals.next().unwrap();
                         match inst.opcode {
-                            OpCode::ADD =>     vals.fold(Ok(r), |a, v| Ok((a? + v)?) )?,
-                            OpCode::SUB =>     vals.fold(Ok(r), |a, v| Ok((a? - v)?) )?,
-                            OpCode::MUL =>     vals.fold(Ok(r), |a, v| Ok((a? * v)?) )?,
-                            OpCode::DIV | _ => vals.fold(Ok(r), |a, v| Ok((a? / v)?) )?,
+                            OpCode::ADD =>     vals.fold(Ok::<_, Error>(r), |a, v| Ok((a? + v)?) )?,
+                            OpCode::SUB =>     vals.fold(Ok::<_, Error>(r), |a, v| Ok((a? - v)?) )?,
+                            OpCode::MUL =>     vals.fold(Ok::<_, Error>(r), |a, v| Ok((a? * v)?) )?,
+                            OpCode::DIV | _ => vals.fold(Ok::<_, Error>(r), |a, v| Ok((a? / v)?) )?,
                         }
                     })
             },
+            OpCode::VEC | OpCode::VRF | OpCode::VST | OpCode::VLN | OpCode::VSL
+            | OpCode::MAP | OpCode::MGT | OpCode::MST | OpCode::MDL
+            | OpCode::MKS | OpCode::MLN | OpCode::SCA | OpCode::SCD => {
+                self.run_collection_op(inst)?;
+            },
+            OpCode::RTD | OpCode::RNW | OpCode::RIS | OpCode::RGT | OpCode::RST => {
+                self.run_record_op(inst)?;
+            },
             OpCode::DSP => {
                 let a = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
 
-                let a = map_as!(*a.deref() => Str(ref s) => s)?;
+                let a = a.print(PrintMode::Display);
 
                 print!("{}", a);
                 trace!("DISPLAY: {}", a);
@@ -325,11 +459,181 @@ impl Job {
         Ok(())
     }
 
+    /// Names `v` after `ident` if it is a procedure still without a name
+    fn name_after(&self, ident: IdentID, v: MemData) -> MemData {
+        match v {
+            MemData::Proc(ref p) | MemData::Lambda(ref p, _) if p.name().is_none() => (),
+            v => return v,
+        }
+        match self.env.ident_name(ident) {
+            Some(name) => v.named(Symbol::intern(&name)),
+            None => v,
+        }
+    }
+
+    /// Record operations, kept apart for the same reason as `run_collection_op`
+    fn run_record_op(&mut self, inst: &Op) -> Result<(), Error> {
+        let symbol = |v: &MemData| map_as!(*v.deref() => Symbol(s) => s);
+        let n = inst.n.unwrap_or(0) as usize;
+        let mut args = match inst.opcode {
+            OpCode::RTD | OpCode::RNW => self.pop_n(n + 1)?,
+            OpCode::RIS | OpCode::RGT => self.pop_n(2)?,
+            _ => self.pop_n(3)?,
+        }.into_iter();
+        let first = args.next().unwrap();
+
+        if inst.opcode == OpCode::RTD {
+            let fields = args.map(|f| symbol(&f)).collect::<Result<Vec<Symbol>, Error>>()?;
+            let rtd = RecordType::new(symbol(&first)?, fields);
+            self.reg_stack.push_back(MemData::RecordType(rtd));
+            return Ok(());
+        }
+
+        let rtd = map_as!(*first.deref() => RecordType(ref t) => t.clone())?;
+        match inst.opcode {
+            OpCode::RNW => {
+                let r = rtd.instance(args.collect())?;
+                self.reg_stack.push_back(MemData::Record(r))
+            },
+            OpCode::RIS => {
+                let is = rtd.check(&args.next().unwrap()).is_ok();
+                self.reg_stack.push_back(MemData::Bool(is))
+            },
+            OpCode::RGT => {
+                let v = rtd.check(&args.next().unwrap())?.get(n)?;
+                self.reg_stack.push_back(v)
+            },
+            _ => {
+                let r = args.next().unwrap();
+                rtd.check(&r)?.set(n, args.next().unwrap())?;
+                self.env.track(&r);
+                if ! inst.mute {
+                    self.reg_stack.push_back(MemData::Nil);
+                }
+            },
+        }
+        Ok(())
+    }
+
+    /// Vector, map and pair mutation operations, kept apart so the frame of `run_instruction`
+    /// (which recursive calls nest) stays small
+    fn run_collection_op(&mut self, inst: &Op) -> Result<(), Error> {
+        match inst.opcode {
+        OpCode::VEC => {
+            let n = inst.n.expect("getting quantifier") as usize;
+            let items = self.pop_n(n)?.into_iter().collect();
+            self.reg_stack.push_back(MemData::vector(items))
+        },
+        OpCode::VRF | OpCode::VST => {
+            // Vector ref | Vector set, over `vector index [value]`
+            let n = if inst.opcode == OpCode::VRF { 2 } else { 3 };
+            let mut args = self.pop_n(n)?.into_iter();
+            let v = args.next().unwrap();
+            let items = map_as!(*v.deref() => Vector(ref items) => items.clone())?;
+            let i = args.next().unwrap().as_index(items.borrow().len(), false)?;
+
+            if let Some(val) = args.next() {
+                items.borrow_mut()[i] = val;
+                self.env.track(&v);
+                if ! inst.mute {
+                    self.reg_stack.push_back(MemData::Nil);
+                }
+            } else {
+                let val = items.borrow()[i].clone();
+                self.reg_stack.push_back(val)
+            }
+        },
+        OpCode::VLN => {
+            let v = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
+            let len = map_as!(*v.deref() => Vector(ref items) => items.borrow().len())?;
+            self.reg_stack.push_back(MemData::Int(len as i64))
+        },
+        OpCode::VSL => {
+            // Copies `vector[start..end]` to a new vector
+            let mut args = self.pop_n(3)?.into_iter();
+            let v = args.next().unwrap();
+            let items = map_as!(*v.deref() => Vector(ref items) => items.clone())?;
+            let items = items.borrow();
+            let start = args.next().unwrap().as_index(items.len(), true)?;
+            let end = args.next().unwrap().as_index(items.len(), true)?;
+            if start > end {
+                return Err(Error::BadRange(start, end));
+            }
+            self.reg_stack.push_back(MemData::vector(items[start..end].to_vec()))
+        },
+        OpCode::MAP => {
+            // The register holds `key value` for each of the <n> pairs
+            let n = inst.n.expect("getting quantifier") as usize;
+            let mut vals = self.pop_n(2 * n)?.into_iter();
+            let mut pairs = Vec::with_capacity(n);
+            while let (Some(k), Some(v)) = (vals.next(), vals.next()) {
+                pairs.push((k, v));
+            }
+            self.reg_stack.push_back(MemData::map(pairs)?)
+        },
+        OpCode::MGT | OpCode::MST | OpCode::MDL => {
+            // Map get | Map set | Map delete, over `map key [value]`
+            let n = if inst.opcode == OpCode::MST { 3 } else { 2 };
+            let mut args = self.pop_n(n)?.into_iter();
+            let (m, k) = (args.next().unwrap(), args.next().unwrap());
+            match inst.opcode {
+                OpCode::MGT => {
+                    let v = m.map_get(&k)?.unwrap_or(MemData::Nil);
+                    self.reg_stack.push_back(v)
+                },
+                _ => {
+                    if let Some(v) = args.next() {
+                        m.map_insert(&k, v)?;
+                        self.env.track(&m);
+                    } else {
+                        m.map_remove(&k)?;
+                    }
+                    if ! inst.mute {
+                        self.reg_stack.push_back(MemData::Nil);
+                    }
+                },
+            }
+        },
+        OpCode::MKS | OpCode::MLN => {
+            let m = self.reg_stack.pop_back().ok_or(Error::IllegalRegisterPop)?;
+            self.reg_stack.push_back(match inst.opcode {
+                OpCode::MKS => MemData::list(m.map_keys()?),
+                _ => MemData::Int(m.map_len()? as i64),
+            })
+        },
+            OpCode::SCA | OpCode::SCD => {
+                // Set car | Set cdr, over `pair value`
+                let mut args = self.pop_n(2)?.into_iter();
+                let (p, v) = (args.next().unwrap(), args.next().unwrap());
+                map_as!(*p.deref() => Pair(ref c) => match inst.opcode {
+                    OpCode::SCA => c.set_car(v),
+                    _ => c.set_cdr(v),
+                })?;
+                self.env.track(&p);
+                if ! inst.mute {
+                    self.reg_stack.push_back(MemData::Nil);
+                }
+            },
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
     pub fn execute(
         &mut self,
         insts: &Procedure,
         env: Option<Environment>) -> Result<(), err::RuntimeError> {
 
+        // Every nested procedure takes a few native frames, so deep recursion
+        // fails here rather than overflowing the stack
+        if self.depth >= MAX_DEPTH {
+            return Err(RuntimeError {
+                instruction: None,
+                instruction_num: None,
+                error: Error::RecursionLimit(MAX_DEPTH),
+            });
+        }
+
         // BEGIN Env swapping
         let mut old_env = None;
         // let env = env.map(
@@ -345,14 +649,17 @@ impl Job {
 
         let initial_len = self.reg_stack.len();
 
-        for (i, inst) in insts.iter().enumerate() {
-            self.run_instruction(&inst)
+        self.depth += 1;
+        let r = insts.iter().enumerate().try_for_each(|(i, inst)| {
+            self.run_instruction(inst)
                 .map_err(|e| RuntimeError {
                     instruction: Some(inst.clone()),
                     instruction_num: Some(i),
                     error: e
-                })?
-        }
+                })
+        });
+        self.depth -= 1;
+        r?;
 
         // BEGIN Env restore
         if let Some(old_env) = old_env {
@@ -373,61 +680,157 @@ impl VM {
         Self {
             //registers: Registers::new(),
             consts: consts,
+            const_index: HashMap::new(),
             memory: mem.clone(),
             jobs: vec![Job::new(mem)],
         }
     }
 
     // return IdentID of the function representing the bin
-    pub fn load(&mut self, bin: Bin, flags: LoadOpts) -> IdentID {
+    pub fn load(&mut self, bin: Bin, flags: LoadOpts) -> Result<IdentID, Error> {
+        verify(&bin).map_err(Error::VerificationFailed)?;
+
         // early reserve our ident for proper offsets
-        let id = self.memory.new_ident_id(None);
+        let id = self.memory.new_ident_id(None)?;
         self.memory.define(id, MemData::Nil).unwrap();
 
-        let (mut insts, idents, mut var_strings, consts) = bin.unpack();
-        let const_ofs = {
-            let o = self.memory.max_const_id().map_or(0, |i| i+1);
-            consts.into_iter().for_each(|v| { self.memory.load_const(v); } );
-            o
-        };
+        let (mut insts, idents, var_strings, consts) = bin.unpack();
 
-        let mut ii = self.memory.new_ident_id(None) - 1;
-        let new_idents = idents.clone().into_iter().map(|i| {
-            ii+=1;
+        // Fresh ids are handed out contiguously so long sessions don't run out of them
+        let mut next = id;
+        let mut new_idents = Vec::with_capacity(idents.len());
+        for i in idents.iter() {
+            let var_str = var_strings.get(i);
             if flags.contains(LoadOpts::REUSE_VAR_STRINGS) {
-                if let Some(s) = var_strings.get(&i) {
-                    if let Some(id) = self.memory.get_ident(s) {
-                        trace!("Reusing id: {} with var_str: {}", id, s);
-                        id
-                    } else {
-                        trace!("Creating new var_str: {} with id: {}", s, id);
-                        self.memory.bind_var_string(s.to_owned(), i+ii);
-                        i+ii
-                    }
-                } else {
-                    i+ii
-                }
-            } else {
-                if let Some(s) = var_strings.get(&i) {
-                    trace!("Creating new var_str: {} with id: {}", s, id);
-                    self.memory.bind_var_string(s.to_owned(), i+ii);
+                if let Some(id) = var_str.and_then(|s| self.memory.get_ident(s)) {
+                    trace!("Reusing id: {} with var_str: {:?}", id, var_str);
+                    new_idents.push(id);
+                    continue;
                 }
-                i+ii
             }
-            // self.memory.new_ident_id(var_strings.remove(&i))
-        } ).collect();
 
-        insts.apply_ident_swaps(idents, new_idents);
-        insts.apply_const_offset(const_ofs);
+            next = match next.checked_add(1) {
+                Some(n) => n,
+                None => {
+                    let _ = self.memory.undefine(&id);
+                    return Err(Error::IdentsExhausted);
+                },
+            };
+            if let Some(s) = var_str {
+                trace!("Creating new var_str: {} with id: {}", s, next);
+                self.memory.bind_var_string(s.to_owned(), next);
+            }
+            new_idents.push(next);
+        }
+
+        let new_consts = self.const_ids(&consts)
+            .inspect_err(|_| { let _ = self.memory.undefine(&id); })?;
+        let relocation = Relocation::new(idents, new_idents, new_consts);
+        insts.relocate(&relocation);
+
+        // Constants seen for the first time got the next ids in order, and are
+        // loaded now that ops inside them can be relocated too
+        for (i, mut v) in consts.into_iter().enumerate() {
+            if relocation.const_id(i) as usize == self.consts.borrow().len() {
+                relocation.apply_data(&mut v);
+                if let Ok(k) = MapKey::new(&v) {
+                    self.const_index.insert(k, relocation.const_id(i));
+                }
+                self.memory.load_const(v);
+            }
+        }
 
         let env = self.memory.clone();
-        self.memory.define(id, MemData::Lambda(insts, env));
+        self.memory.define(id, MemData::Lambda(insts, env))?;
 
-        id
+        Ok(id)
+    }
+
+    /// The ids `consts` get once loaded, reusing equal constants loaded before
+    /// when they are hashable so that loading the same code over and over
+    /// doesn't run out of const ids
+    fn const_ids(&self, consts: &[MemData]) -> Result<Vec<ConstID>, Error> {
+        let mut next = self.consts.borrow().len();
+        let mut seen: HashMap<MapKey, ConstID> = HashMap::new();
+        let mut ids = Vec::with_capacity(consts.len());
+
+        for v in consts {
+            let key = MapKey::new(v).ok();
+            let known = key.as_ref()
+                .and_then(|k| self.const_index.get(k).or_else(|| seen.get(k)))
+                .cloned();
+            if let Some(i) = known {
+                ids.push(i);
+                continue;
+            }
+
+            if next > ConstID::MAX as usize {
+                return Err(Error::ConstantsExhausted);
+            }
+            if let Some(ref k) = key {
+                seen.insert(k.clone(), next as ConstID);
+            }
+            ids.push(next as ConstID);
+            next += 1;
+        }
+        Ok(ids)
+    }
+
+    /// Forgets the function `load` returned, freeing its id for later loads;
+    /// what it defined stays around
+    pub fn unload(&mut self, id: &IdentID) -> Result<(), Error> {
+        self.memory.undefine(id)
     }
 
     pub fn call(&mut self, id: &IdentID) -> Result<MemData, self::RuntimeError> {
-        self.jobs[0].call(id)
+        let job = &mut self.jobs[0];
+
+        // Snapshot the job so that a failed call leaves it usable for the next one
+        let env = job.env.clone();
+        let depth = job.reg_stack.len();
+
+        let r = job.call(id).inspect_err(|_| {
+            job.env = env;
+            job.recording = 0;
+            if job.reg_stack.len() > depth {
+                let _ = job.reg_stack.split_off(depth);
+            }
+        });
+
+        if self.memory.heap().borrow().collection_due() {
+            self.gc();
+        }
+        r
+    }
+
+    /// Frees the reference cycles nothing reaches anymore, see `gc.rs`
+    pub fn gc(&mut self) -> HeapStats {
+        let mut roots = Vec::new();
+        self.memory.gc_nodes(&mut roots);
+        roots.extend(self.consts.borrow().iter().map(|c| Node::Value(Rc::clone(c))));
+        for job in self.jobs.iter() {
+            job.env.gc_nodes(&mut roots);
+            job.reg_stack.iter().for_each(|v| Node::of_data(v, &mut roots));
+        }
+
+        let heap = self.memory.heap();
+        let stats = heap.borrow_mut().collect(roots);
+        debug!("gc: {:?}", stats);
+        stats
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.memory.heap().borrow().stats()
+    }
+
+    /// Named variables currently defined in the global scope, sorted by name
+    pub fn bindings(&self) -> Vec<(String, MemData)> {
+        let mut r: Vec<(String, MemData)> = self.memory.var_strings()
+            .into_iter()
+            .filter_map(|(s, id)| self.memory.get(&id).ok().map(|v| (s, v)))
+            .collect();
+        r.sort_by(|a, b| a.0.cmp(&b.0));
+        r
     }
 
 }
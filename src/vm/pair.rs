@@ -0,0 +1,103 @@
+//! Pairs, shared and mutable
+//!
+//! A `Cons` is a reference to one cell: cloning it (and so taking the car or
+//! cdr of a list) never copies the rest of the list, and `set_car`/`set_cdr`
+//! are seen by every holder of the pair.
+
+use super::MemData;
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{
+    Rc,
+    Weak,
+};
+
+#[derive(Clone)]
+pub struct Cons(Rc<RefCell<Cell>>);
+
+/// A `Cons` that doesn't keep its cell alive, see `gc`
+pub struct WeakCons(Weak<RefCell<Cell>>);
+
+struct Cell {
+    car: MemData,
+    cdr: MemData,
+}
+
+impl Cons {
+    pub fn new(car: MemData, cdr: MemData) -> Cons {
+        Cons(Rc::new(RefCell::new(Cell { car, cdr })))
+    }
+
+    pub fn car(&self) -> MemData {
+        self.0.borrow().car.clone()
+    }
+
+    pub fn cdr(&self) -> MemData {
+        self.0.borrow().cdr.clone()
+    }
+
+    pub fn set_car(&self, v: MemData) {
+        self.0.borrow_mut().car = v;
+    }
+
+    pub fn set_cdr(&self, v: MemData) {
+        self.0.borrow_mut().cdr = v;
+    }
+
+    /// Whether both refer to the very same cell
+    pub fn ptr_eq(&self, other: &Cons) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Identifies the cell, e.g. to notice cycles
+    pub fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.0) as *const ()
+    }
+
+    /// Number of `Cons` referring to the cell
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.0)
+    }
+
+    pub fn downgrade(&self) -> WeakCons {
+        WeakCons(Rc::downgrade(&self.0))
+    }
+}
+
+impl WeakCons {
+    pub fn upgrade(&self) -> Option<Cons> {
+        self.0.upgrade().map(Cons)
+    }
+}
+
+impl PartialEq for Cons {
+    fn eq(&self, other: &Cons) -> bool {
+        // Cycle-safe, see `equal.rs`
+        MemData::Pair(self.clone()) == MemData::Pair(other.clone())
+    }
+}
+
+impl Drop for Cell {
+    /// Takes apart the rest of a list no one else holds one pair at a time, so
+    /// dropping a long list doesn't nest a call per pair
+    fn drop(&mut self) {
+        let mut rest = ::std::mem::replace(&mut self.cdr, MemData::Nil);
+        while let MemData::Pair(c) = rest {
+            rest = match Rc::try_unwrap(c.0) {
+                Ok(mut cell) => ::std::mem::replace(&mut cell.get_mut().cdr, MemData::Nil),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for Cons {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let c = self.0.borrow();
+        f.debug_struct("Cons")
+            .field("car", &c.car)
+            .field("cdr", &c.cdr)
+            .finish()
+    }
+}
@@ -0,0 +1,187 @@
+//! Lisp syntax for values
+//!
+//! `Display` mode shows strings and chars as they are, `Write` mode quotes
+//! them so that the output reads back as the same datum. A pair, vector, map or
+//! record met again while it is still being printed shows as `#<cycle>`.
+
+use super::{
+    Cons,
+    MemData,
+};
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::rc::Rc;
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum PrintMode {
+    Display,
+    Write,
+}
+
+impl MemData {
+    pub fn print(&self, mode: PrintMode) -> String {
+        let mut p = Printer { mode, out: String::new(), path: Vec::new(), on_path: HashSet::new() };
+        p.value(self);
+        p.out
+    }
+}
+
+struct Printer {
+    mode: PrintMode,
+    out: String,
+    /// Pairs, vectors and maps being printed, outermost first
+    path: Vec<*const ()>,
+    /// The same, to tell quickly whether one is being printed
+    on_path: HashSet<*const ()>,
+}
+
+impl Printer {
+    fn value(&mut self, v: &MemData) {
+        match *v.deref() {
+            MemData::Str(ref s) => self.string(s),
+            MemData::Char(c) => self.character(c),
+            MemData::Symbol(s) => self.out.push_str(s.as_str()),
+            MemData::Int(i) => self.out.push_str(&i.to_string()),
+            MemData::BigInt(ref b) => self.out.push_str(&b.to_string()),
+            MemData::Float(f) => { let _ = write!(self.out, "{:?}", f); },
+            MemData::Bool(b) => self.out.push_str(if b { "#t" } else { "#f" }),
+            MemData::Nil => self.out.push_str("()"),
+            MemData::Pair(ref c) => self.list(c),
+            MemData::Vector(ref items) => {
+                let depth = self.path.len();
+                if self.enter(Rc::as_ptr(items) as *const ()) {
+                    self.out.push_str("#(");
+                    for (i, item) in items.borrow().iter().enumerate() {
+                        if i > 0 {
+                            self.out.push(' ');
+                        }
+                        self.value(item);
+                    }
+                    self.out.push(')');
+                    self.leave(depth);
+                }
+            },
+            MemData::Map(ref map) => {
+                let depth = self.path.len();
+                if self.enter(Rc::as_ptr(map) as *const ()) {
+                    // Sorted by key so the output doesn't depend on the hashing
+                    let mut entries: Vec<(String, MemData)> = map.borrow().iter()
+                        .map(|(k, v)| (k.to_data().print(self.mode), v.clone()))
+                        .collect();
+                    entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+                    self.out.push_str("#<map");
+                    for (k, v) in entries {
+                        let _ = write!(self.out, " ({} . ", k);
+                        self.value(&v);
+                        self.out.push(')');
+                    }
+                    self.out.push('>');
+                    self.leave(depth);
+                }
+            },
+            MemData::RecordType(ref t) => { let _ = write!(self.out, "#<record-type {}>", t.name()); },
+            MemData::Record(ref r) => {
+                let depth = self.path.len();
+                if self.enter(r.as_ptr()) {
+                    let _ = write!(self.out, "#<{}", r.rtd().name());
+                    for (field, v) in r.rtd().fields().iter().zip(r.slots()) {
+                        let _ = write!(self.out, " {}: ", field);
+                        self.value(&v);
+                    }
+                    self.out.push('>');
+                    self.leave(depth);
+                }
+            },
+            MemData::Lambda(ref p, _) | MemData::Proc(ref p) => match p.name() {
+                Some(name) => { let _ = write!(self.out, "#<procedure {}>", name); },
+                None => self.out.push_str("#<procedure>"),
+            },
+            MemData::Inst(ref op) => { let _ = write!(self.out, "#<instruction {:?}>", op.opcode); },
+            MemData::Pointer(..) => unreachable!("deref never returns a pointer"),
+        }
+    }
+
+    /// Adds `ptr` to the path, or prints `#<cycle>` if it is already there
+    fn enter(&mut self, ptr: *const ()) -> bool {
+        if !self.on_path.insert(ptr) {
+            self.out.push_str("#<cycle>");
+            return false;
+        }
+        self.path.push(ptr);
+        true
+    }
+
+    /// Takes what was entered since the path was `depth` long off it again
+    fn leave(&mut self, depth: usize) {
+        for ptr in self.path.drain(depth..) {
+            self.on_path.remove(&ptr);
+        }
+    }
+
+    /// `(a b c)`, or `(a b . c)` for an improper list
+    fn list(&mut self, first: &Cons) {
+        let depth = self.path.len();
+        if !self.enter(first.as_ptr()) {
+            return;
+        }
+        self.out.push('(');
+        let mut cell = first.clone();
+        loop {
+            self.value(&cell.car());
+            let cdr = cell.cdr();
+            match *cdr.deref() {
+                MemData::Nil => break,
+                MemData::Pair(ref next) if !self.on_path.contains(&next.as_ptr()) => {
+                    self.out.push(' ');
+                    self.enter(next.as_ptr());
+                    cell = next.clone();
+                },
+                ref rest => {
+                    self.out.push_str(" . ");
+                    self.value(rest);
+                    break;
+                },
+            }
+        }
+        self.out.push(')');
+        self.leave(depth);
+    }
+
+    fn string(&mut self, s: &str) {
+        if self.mode == PrintMode::Display {
+            self.out.push_str(s);
+            return;
+        }
+        self.out.push('"');
+        for c in s.chars() {
+            match c {
+                '"'  => self.out.push_str("\\\""),
+                '\\' => self.out.push_str("\\\\"),
+                '\n' => self.out.push_str("\\n"),
+                '\t' => self.out.push_str("\\t"),
+                '\r' => self.out.push_str("\\r"),
+                '\0' => self.out.push_str("\\0"),
+                c => self.out.push(c),
+            }
+        }
+        self.out.push('"');
+    }
+
+    fn character(&mut self, c: char) {
+        if self.mode == PrintMode::Display {
+            self.out.push(c);
+            return;
+        }
+        let _ = match c {
+            ' '  => write!(self.out, "#\\space"),
+            '\n' => write!(self.out, "#\\newline"),
+            '\t' => write!(self.out, "#\\tab"),
+            '\r' => write!(self.out, "#\\return"),
+            '\0' => write!(self.out, "#\\nul"),
+            c if c.is_control() || c.is_whitespace() => write!(self.out, "#\\x{:x}", c as u32),
+            c => write!(self.out, "#\\{}", c),
+        };
+    }
+}
@@ -0,0 +1,157 @@
+//! Records made by `define-record-type`
+//!
+//! A `RecordType` names its fields; a `Record` is an instance holding one slot
+//! per field. Both are shared like pairs: clones refer to the same object, and
+//! setting a field is seen by every holder of the record.
+
+use super::{
+    MemData,
+    Symbol,
+    Type,
+    Error,
+};
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::{
+    Rc,
+    Weak,
+};
+
+/// Two record types are the same only if they are the same object, so that
+/// defining a type twice gives two distinct types
+#[derive(Clone)]
+pub struct RecordType(Rc<TypeInfo>);
+
+struct TypeInfo {
+    name: Symbol,
+    fields: Vec<Symbol>,
+}
+
+#[derive(Clone)]
+pub struct Record {
+    rtd: RecordType,
+    slots: Rc<RefCell<Vec<MemData>>>,
+}
+
+/// A `Record` that doesn't keep its slots alive, see `gc`
+pub struct WeakRecord {
+    rtd: RecordType,
+    slots: Weak<RefCell<Vec<MemData>>>,
+}
+
+impl RecordType {
+    pub fn new(name: Symbol, fields: Vec<Symbol>) -> RecordType {
+        RecordType(Rc::new(TypeInfo { name, fields }))
+    }
+
+    pub fn name(&self) -> Symbol {
+        self.0.name
+    }
+
+    pub fn fields(&self) -> &[Symbol] {
+        &self.0.fields
+    }
+
+    pub fn ptr_eq(&self, other: &RecordType) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// Makes an instance; there must be exactly one value per field
+    pub fn instance(&self, values: Vec<MemData>) -> Result<Record, Error> {
+        if values.len() != self.fields().len() {
+            return Err(Error::WrongArgumentCount(self.fields().len(), values.len()));
+        }
+        Ok(Record { rtd: self.clone(), slots: Rc::new(RefCell::new(values)) })
+    }
+
+    /// `v` as an instance of this type, or a type error naming both types
+    pub fn check<'a>(&self, v: &'a MemData) -> Result<&'a Record, Error> {
+        match *v.deref() {
+            MemData::Record(ref r) if r.rtd.ptr_eq(self) => Ok(r),
+            MemData::Record(ref r) =>
+                Err(Error::TypeError(Type::Record, Type::Record, Some((self.name(), r.rtd.name())))),
+            ref v => Err(v.wrong_type(Type::Record)),
+        }
+    }
+}
+
+impl PartialEq for RecordType {
+    fn eq(&self, other: &RecordType) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl fmt::Debug for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RecordType({})", self.name())
+    }
+}
+
+impl Record {
+    pub fn rtd(&self) -> &RecordType {
+        &self.rtd
+    }
+
+    pub fn get(&self, i: usize) -> Result<MemData, Error> {
+        self.slots.borrow().get(i).cloned()
+            .ok_or_else(|| Error::IndexOutOfRange(i as i64, self.rtd.fields().len()))
+    }
+
+    pub fn set(&self, i: usize, v: MemData) -> Result<(), Error> {
+        match self.slots.borrow_mut().get_mut(i) {
+            Some(slot) => { *slot = v; Ok(()) },
+            None => Err(Error::IndexOutOfRange(i as i64, self.rtd.fields().len())),
+        }
+    }
+
+    pub fn slots(&self) -> Vec<MemData> {
+        self.slots.borrow().clone()
+    }
+
+    /// Whether both refer to the very same instance
+    pub fn ptr_eq(&self, other: &Record) -> bool {
+        Rc::ptr_eq(&self.slots, &other.slots)
+    }
+
+    /// Identifies the instance, e.g. to notice cycles
+    pub fn as_ptr(&self) -> *const () {
+        Rc::as_ptr(&self.slots) as *const ()
+    }
+
+    /// Number of `Record`s referring to the instance
+    pub fn strong_count(&self) -> usize {
+        Rc::strong_count(&self.slots)
+    }
+
+    /// Sets every field to nil
+    pub fn clear(&self) {
+        self.slots.borrow_mut().iter_mut().for_each(|v| *v = MemData::Nil);
+    }
+
+    pub fn downgrade(&self) -> WeakRecord {
+        WeakRecord { rtd: self.rtd.clone(), slots: Rc::downgrade(&self.slots) }
+    }
+}
+
+impl WeakRecord {
+    pub fn upgrade(&self) -> Option<Record> {
+        self.slots.upgrade().map(|slots| Record { rtd: self.rtd.clone(), slots })
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Record) -> bool {
+        // Cycle-safe, see `equal.rs`
+        MemData::Record(self.clone()) == MemData::Record(other.clone())
+    }
+}
+
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Record")
+            .field("type", &self.rtd.name())
+            .field("slots", &*self.slots.borrow())
+            .finish()
+    }
+}
@@ -0,0 +1,124 @@
+//! String operations run by `STR n`, `n` being the index of the operation in
+//! `STR_OPS`
+//!
+//! Strings are sequences of `Char`s: lengths and indices count characters,
+//! not the bytes of their UTF-8 encoding.
+
+use super::{
+    MemData,
+    Type,
+    Error,
+};
+
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub enum StrOp {
+    /// `str` -> number of chars
+    Length,
+    /// `str start end` -> the chars in [start, end)
+    Substring,
+    /// `str needle` -> index of the first occurrence of `needle`, or #f
+    IndexOf,
+    /// `str index` -> the char at `index`
+    CharAt,
+    /// `str separator` -> list of the parts between separators
+    Split,
+    /// `list separator` -> the strings of `list`, separated by `separator`
+    Join,
+    Upcase,
+    Downcase,
+    /// Strips whitespace at both ends
+    Trim,
+    /// `str prefix` -> bool
+    StartsWith,
+    /// `str suffix` -> bool
+    EndsWith,
+}
+
+/// Every string operation, indexed by its quantifier
+pub const STR_OPS: &[StrOp] = &[
+    StrOp::Length, StrOp::Substring, StrOp::IndexOf, StrOp::CharAt, StrOp::Split,
+    StrOp::Join, StrOp::Upcase, StrOp::Downcase, StrOp::Trim, StrOp::StartsWith,
+    StrOp::EndsWith,
+];
+
+impl StrOp {
+    pub fn from_quantif(n: u32) -> Option<StrOp> {
+        STR_OPS.get(n as usize).cloned()
+    }
+
+    pub fn quantif(self) -> u32 {
+        STR_OPS.iter().position(|op| *op == self).unwrap() as u32
+    }
+
+    /// Number of values taken from the register
+    pub fn arity(self) -> usize {
+        match self {
+            StrOp::Length | StrOp::Upcase | StrOp::Downcase | StrOp::Trim => 1,
+            StrOp::Substring => 3,
+            _ => 2,
+        }
+    }
+
+    /// Runs the operation on `args`, in the order they were pushed
+    pub fn run(self, args: &[MemData]) -> Result<MemData, Error> {
+        let s = as_str(&args[0]);
+        Ok(match self {
+            StrOp::Length => MemData::Int(s?.chars().count() as i64),
+            StrOp::Substring => {
+                let s = s?;
+                let len = s.chars().count();
+                let start = args[1].as_index(len, true)?;
+                let end = args[2].as_index(len, true)?;
+                if start > end {
+                    return Err(Error::BadRange(start, end));
+                }
+                MemData::Str(s.chars().skip(start).take(end - start).collect())
+            },
+            StrOp::IndexOf => {
+                let s = s?;
+                match s.find(as_str(&args[1])?) {
+                    Some(at) => MemData::Int(s[..at].chars().count() as i64),
+                    None => MemData::Bool(false),
+                }
+            },
+            StrOp::CharAt => {
+                let s = s?;
+                let i = args[1].as_index(s.chars().count(), false)?;
+                MemData::Char(s.chars().nth(i).unwrap())
+            },
+            StrOp::Split => {
+                let (s, sep) = (s?, as_str(&args[1])?);
+                let parts: Vec<MemData> = if sep.is_empty() {
+                    s.chars().map(|c| MemData::Str(c.to_string())).collect()
+                } else {
+                    s.split(sep).map(|p| MemData::Str(p.to_owned())).collect()
+                };
+                MemData::list(parts)
+            },
+            StrOp::Join => {
+                let sep = as_str(&args[1])?;
+                let items = args[0].list_items().ok_or_else(|| args[0].wrong_type(Type::Pair))?;
+                let mut r = String::new();
+                for (i, v) in items.iter().enumerate() {
+                    if i > 0 {
+                        r.push_str(sep);
+                    }
+                    r.push_str(as_str(v)?);
+                }
+                MemData::Str(r)
+            },
+            StrOp::Upcase => MemData::Str(s?.to_uppercase()),
+            StrOp::Downcase => MemData::Str(s?.to_lowercase()),
+            StrOp::Trim => MemData::Str(s?.trim().to_owned()),
+            StrOp::StartsWith => MemData::Bool(s?.starts_with(as_str(&args[1])?)),
+            StrOp::EndsWith => MemData::Bool(s?.ends_with(as_str(&args[1])?)),
+        })
+    }
+}
+
+fn as_str(v: &MemData) -> Result<&str, Error> {
+    match *v.deref() {
+        MemData::Str(ref s) => Ok(s),
+        ref v => Err(v.wrong_type(Type::Str)),
+    }
+}
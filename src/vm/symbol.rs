@@ -0,0 +1,57 @@
+//! Interned symbols
+//!
+//! Every distinct name is stored once in a process wide table, so a `Symbol`
+//! is a plain index: copying and comparing one never touches the name.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{
+    Mutex,
+    OnceLock,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Symbol(u32);
+
+#[derive(Default)]
+struct Interner {
+    /// Names are never freed, which lets `as_str` hand out `'static` borrows
+    names: Vec<&'static str>,
+    ids: HashMap<&'static str, Symbol>,
+}
+
+fn interner() -> &'static Mutex<Interner> {
+    static INTERNER: OnceLock<Mutex<Interner>> = OnceLock::new();
+    INTERNER.get_or_init(Default::default)
+}
+
+impl Symbol {
+    /// The symbol named `name`, added to the table on first use
+    pub fn intern(name: &str) -> Symbol {
+        let mut t = interner().lock().unwrap();
+        if let Some(sym) = t.ids.get(name) {
+            return *sym;
+        }
+        let name: &'static str = Box::leak(name.to_owned().into_boxed_str());
+        let sym = Symbol(t.names.len() as u32);
+        t.names.push(name);
+        t.ids.insert(name, sym);
+        sym
+    }
+
+    pub fn as_str(self) -> &'static str {
+        interner().lock().unwrap().names[self.0 as usize]
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
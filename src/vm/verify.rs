@@ -0,0 +1,392 @@
+//! Static checks run on a `Bin` before it is loaded
+//!
+//! The verifier walks each procedure once, keeping an upper bound of the
+//! register stack so that it only reports pops that are certain to fail.
+//! Bodies recorded by `REC` are verified on their own: they run on top of
+//! whatever their caller left in the register, so they may pop values they
+//! didn't push (lambda arguments, for one). So are the ops inside `Proc` and
+//! `Inst` constants, which run just like recorded ones.
+
+use super::{
+    Bin,
+    Op,
+    OpCode,
+    MemData,
+    ConstID,
+    StrOp,
+    CharOp,
+};
+
+use std::fmt;
+
+/// One problem found by the verifier
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyError {
+    /// The constant holding the instruction, if it isn't one of the bin's own
+    pub constant: Option<ConstID>,
+    pub instruction_num: usize,
+    pub instruction: Op,
+    pub problem: &'static str,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(c) = self.constant {
+            write!(f, "constant(#{}): ", c)?;
+        }
+        write!(f, "instruction(#{}): `{:?}`: {}", self.instruction_num, self.instruction, self.problem)
+    }
+}
+
+/// What the verifier knows about one register slot
+#[derive(Clone, Copy, PartialEq)]
+enum Slot {
+    Val,
+    /// Recorded by the `REC` at this index, whose body has the given net effect
+    Inst(usize, Option<isize>),
+    /// A procedure with a known (or unknown) net effect on the register
+    Proc(Option<isize>),
+}
+
+struct Verifier<'a> {
+    bin: &'a Bin,
+    ops: &'a [Op],
+    constant: Option<ConstID>,
+    problems: Vec<VerifyError>,
+}
+
+/// State of the register while walking one procedure
+struct Register {
+    /// `None` once the depth can't be bounded anymore
+    slots: Option<Vec<Slot>>,
+    /// Values taken from below the start of the procedure
+    borrowed: isize,
+    /// Cleared once `slots` may hold more values than the real register,
+    /// after which slot kinds can't be trusted either
+    exact: bool,
+    /// Bodies may pop what their caller left; the top level may not
+    may_borrow: bool,
+}
+
+impl Register {
+    fn push(&mut self, slot: Slot, n: usize) {
+        if let Some(ref mut s) = self.slots {
+            s.extend((0..n).map(|_| slot));
+        }
+    }
+
+    /// Pops `n` slots, or returns `Err` if that's certain to underflow
+    fn pop(&mut self, n: usize) -> Result<Vec<Slot>, ()> {
+        let may_borrow = self.may_borrow;
+        let exact = self.exact;
+        let mut borrowed = 0;
+        let r = match self.slots {
+            Some(ref mut s) => {
+                if s.len() < n {
+                    if !may_borrow {
+                        s.clear();
+                        return Err(());
+                    }
+                    borrowed = (n - s.len()) as isize;
+                    let mut r = vec![Slot::Val; n - s.len()];
+                    r.append(s);
+                    r
+                } else {
+                    let at = s.len() - n;
+                    s.split_off(at)
+                }
+            },
+            None => vec![Slot::Val; n],
+        };
+        self.borrowed += borrowed;
+        if exact { Ok(r) } else { Ok(r.into_iter().map(|_| Slot::Val).collect()) }
+    }
+
+    /// Applies the net effect of running a body on top of this register
+    fn run(&mut self, net: Option<isize>) -> Result<(), ()> {
+        match net {
+            Some(n) if n >= 0 => self.push(Slot::Val, n as usize),
+            Some(n) => { self.pop((-n) as usize)?; },
+            None => self.slots = None,
+        }
+        Ok(())
+    }
+
+    fn net(&self) -> Option<isize> {
+        self.slots.as_ref().map(|s| s.len() as isize - self.borrowed)
+    }
+}
+
+fn net_of(slot: Slot) -> Option<isize> {
+    match slot {
+        Slot::Proc(net) => net,
+        _ => None,
+    }
+}
+
+impl<'a> Verifier<'a> {
+    fn problem(&mut self, i: usize, problem: &'static str) {
+        self.problems.push(VerifyError {
+            constant: self.constant,
+            instruction_num: i,
+            instruction: self.ops[i].clone(),
+            problem,
+        });
+    }
+
+    /// Verifies `ops[start..end]`, returning its net effect on the register if known
+    fn procedure(&mut self, start: usize, end: usize, may_borrow: bool) -> Option<isize> {
+        let mut reg = Register { slots: Some(Vec::new()), borrowed: 0, exact: true, may_borrow };
+        let mut frames = Vec::new();
+
+        let ops = self.ops;
+        let mut i = start;
+        while i < end {
+            let op = &ops[i];
+            self.operands(i, op);
+
+            let n = op.n.map(|n| n as usize);
+            let underflow = match op.opcode {
+                OpCode::PSS => { frames.push(i); Ok(()) },
+                OpCode::PPS => {
+                    if frames.pop().is_none() {
+                        self.problem(i, "PPS without a matching PSS");
+                    }
+                    Ok(())
+                },
+
+                OpCode::REC => {
+                    let n = n.unwrap_or(1);
+                    if i + n >= end {
+                        self.problem(i, "REC records past the end of the procedure");
+                        return None;
+                    }
+                    let net = self.procedure(i + 1, i + 1 + n, true);
+                    reg.push(Slot::Inst(i, net), n);
+                    i += n;
+                    Ok(())
+                },
+                OpCode::LMB | OpCode::PRC | OpCode::CLL if n.is_some() && op.ident.is_none() => {
+                    let n = n.unwrap();
+                    reg.pop(n).and_then(|slots| {
+                        // A whole `REC n` body, as the code generator lays them out
+                        let net = match slots.first() {
+                            Some(&Slot::Inst(rec, net))
+                                if slots.iter().all(|s| *s == Slot::Inst(rec, net))
+                                && ops[rec].n.unwrap_or(1) as usize == n => net,
+                            _ => None,
+                        };
+                        match op.opcode {
+                            OpCode::LMB => { reg.push(Slot::Val, 1); Ok(()) },
+                            OpCode::PRC => { reg.push(Slot::Proc(net), 1); Ok(()) },
+                            // Runs in a frame of its own, leaving its net effect behind
+                            _ => reg.run(net),
+                        }
+                    })
+                },
+                OpCode::LMB | OpCode::PRC => {
+                    self.problem(i, "missing quantifier");
+                    Ok(())
+                },
+                OpCode::DFN => match n {
+                    Some(n) => reg.pop(n).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+                    None => { self.problem(i, "missing quantifier"); Ok(()) },
+                },
+                OpCode::CAP => reg.pop(1).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::RRR => reg.pop(n.unwrap_or(1)).map(|_| ()),
+
+                OpCode::DVR | OpCode::SET => {
+                    let popped = if op.val.is_some() { Ok(Vec::new()) } else { reg.pop(1) };
+                    if !op.mute { reg.push(Slot::Val, 1); }
+                    popped.map(|_| ())
+                },
+                OpCode::LVR => { reg.push(Slot::Val, 1); Ok(()) },
+
+                OpCode::IFT => reg.pop(2).and_then(|s| {
+                    match net_of(s[0]) {
+                        Some(0) => Ok(()),
+                        // The register differs depending on the branch taken
+                        Some(net) => {
+                            reg.exact = false;
+                            reg.run(Some(net.max(0)))
+                        },
+                        None => reg.run(None),
+                    }
+                }),
+                OpCode::IFE => reg.pop(3).and_then(|s| {
+                    match (net_of(s[0]), net_of(s[1])) {
+                        (Some(a), Some(b)) if a == b => reg.run(Some(a)),
+                        (Some(a), Some(b)) => {
+                            reg.exact = false;
+                            reg.run(Some(a.max(b)))
+                        },
+                        _ => reg.run(None),
+                    }
+                }),
+
+                OpCode::CLL => {
+                    if op.ident.is_none() {
+                        self.problem(i, "CLL needs an identifier or a quantifier");
+                    }
+                    // The callee takes its arguments from the register
+                    reg.exact = false;
+                    reg.push(Slot::Val, 1);
+                    Ok(())
+                },
+
+                OpCode::CAN | OpCode::COR => match n {
+                    Some(n) => reg.pop(n).and_then(|s| {
+                        // Every body has to leave exactly its one value behind
+                        if s.iter().all(|p| net_of(*p) == Some(1)) {
+                            reg.push(Slot::Val, 1);
+                            Ok(())
+                        } else {
+                            reg.run(None)
+                        }
+                    }),
+                    None => { self.problem(i, "missing quantifier"); Ok(()) },
+                },
+                OpCode::CGT | OpCode::CLT | OpCode::CEQ | OpCode::CAT => {
+                    match n {
+                        Some(n) => reg.pop(n).map(|_| reg.push(Slot::Val, 1)),
+                        None => { self.problem(i, "missing quantifier"); Ok(()) },
+                    }
+                },
+                OpCode::CNV | OpCode::CNT | OpCode::CAR | OpCode::CDR => {
+                    if op.opcode == OpCode::CNV && op.typ.is_none() {
+                        self.problem(i, "CNV without a target type");
+                    }
+                    if op.ident.is_some() {
+                        reg.push(Slot::Val, 1);
+                        Ok(())
+                    } else {
+                        let n = match (op.opcode.clone(), n) {
+                            (OpCode::CNV, None) => {
+                                self.problem(i, "missing quantifier");
+                                0
+                            },
+                            (_, n) => n.unwrap_or(1),
+                        };
+                        reg.pop(n).map(|_| reg.push(Slot::Val, n))
+                    }
+                },
+                OpCode::CNS | OpCode::CEI | OpCode::CEV | OpCode::CES =>
+                    reg.pop(2).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::STR => match n.and_then(|n| StrOp::from_quantif(n as u32)) {
+                    Some(s) => reg.pop(s.arity()).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "STR without a valid string operation"); Ok(()) },
+                },
+                OpCode::CHR => match n.and_then(|n| CharOp::from_quantif(n as u32)) {
+                    Some(_) => reg.pop(1).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "CHR without a valid char operation"); Ok(()) },
+                },
+                OpCode::ADD | OpCode::SUB | OpCode::MUL | OpCode::DIV => {
+                    let n = if op.ident.is_some() { 1 } else { n.unwrap_or(1) };
+                    reg.pop(n).map(|_| reg.push(Slot::Val, 1))
+                },
+                OpCode::DSP => reg.pop(1).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+
+                OpCode::VEC => match n {
+                    Some(n) => reg.pop(n).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "missing quantifier"); Ok(()) },
+                },
+                OpCode::VRF => reg.pop(2).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::VST => reg.pop(3).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+                OpCode::VLN => reg.pop(1).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::VSL => reg.pop(3).map(|_| reg.push(Slot::Val, 1)),
+
+                OpCode::MAP => match n {
+                    Some(n) => reg.pop(2 * n).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "missing quantifier"); Ok(()) },
+                },
+                OpCode::MGT => reg.pop(2).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::MST => reg.pop(3).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+                OpCode::MDL => reg.pop(2).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+                OpCode::MKS | OpCode::MLN => reg.pop(1).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::SCA | OpCode::SCD => reg.pop(2).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+
+                OpCode::RTD | OpCode::RNW => match n {
+                    Some(n) => reg.pop(n + 1).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "missing quantifier"); Ok(()) },
+                },
+                OpCode::RIS => reg.pop(2).map(|_| reg.push(Slot::Val, 1)),
+                OpCode::RGT => match n {
+                    Some(_) => reg.pop(2).map(|_| reg.push(Slot::Val, 1)),
+                    None => { self.problem(i, "missing field index"); Ok(()) },
+                },
+                OpCode::RST => match n {
+                    Some(_) => reg.pop(3).map(|_| if !op.mute { reg.push(Slot::Val, 1) }),
+                    None => { self.problem(i, "missing field index"); Ok(()) },
+                },
+            };
+
+            if underflow.is_err() {
+                self.problem(i, "pops more values than the register holds");
+            }
+            i += 1;
+        }
+
+        for pss in frames {
+            self.problem(pss, "PSS without a matching PPS");
+        }
+        reg.net()
+    }
+
+    /// Verifies the ops inside constant `c`, whose value (or part of it) is `v`
+    fn constant(&mut self, c: ConstID, v: &MemData) {
+        let mut v = v.clone();
+        // Lists are walked in a loop so long ones don't nest calls
+        while let MemData::Pair(p) = v {
+            self.constant(c, &p.car());
+            v = p.cdr();
+        }
+
+        let ops = match v {
+            MemData::Inst(ref op) => ::std::slice::from_ref(op),
+            MemData::Proc(ref p) => p.as_slice(),
+            _ => return,
+        };
+        let mut nested = Verifier { bin: self.bin, ops, constant: Some(c), problems: Vec::new() };
+        // Called or recorded like a body, on top of what the caller left
+        nested.procedure(0, ops.len(), true);
+        self.problems.append(&mut nested.problems);
+    }
+
+    fn operands(&mut self, i: usize, op: &Op) {
+        if let Some(ident) = op.ident {
+            if !self.bin.idents().contains(&ident) {
+                self.problem(i, "identifier is not declared by the bin");
+            }
+        }
+        if let Some(val) = op.val {
+            if val as usize >= self.bin.consts().len() {
+                self.problem(i, "constant is not part of the bin");
+            }
+        }
+        let needs_ident = match op.opcode {
+            OpCode::DVR | OpCode::DFN | OpCode::SET => true,
+            OpCode::LVR => op.val.is_none(),
+            _ => false,
+        };
+        if needs_ident && op.ident.is_none() {
+            self.problem(i, "missing identifier");
+        }
+    }
+}
+
+/// Checks a bin for anything that would make the VM panic or misbehave
+pub fn verify(bin: &Bin) -> Result<(), Vec<VerifyError>> {
+    let ops = bin.insts().as_slice();
+    let mut v = Verifier { bin, ops, constant: None, problems: Vec::new() };
+    v.procedure(0, ops.len(), false);
+    for (c, val) in bin.consts().iter().enumerate() {
+        v.constant(c as ConstID, val);
+    }
+
+    if v.problems.is_empty() {
+        Ok(())
+    } else {
+        v.problems.sort_by_key(|p| (p.constant, p.instruction_num));
+        Err(v.problems)
+    }
+}